@@ -0,0 +1,118 @@
+//! Classification of conntrack addresses: linear scan with `Subnet::contains` vs. the prefix trie
+//! of `LocalNetworks`. Run with `cargo bench -p nftracker`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// The server is a binary crate, so the modules are compiled into the benchmark directly
+#[allow(dead_code, unused_imports)]
+#[path = "../src/common/networks.rs"]
+mod networks;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/common/parse.rs"]
+mod parse;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/common/prefix_trie.rs"]
+mod prefix_trie;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/common/subnet.rs"]
+mod subnet;
+
+use networks::LocalNetworks;
+use prefix_trie::PrefixTrie;
+use subnet::Subnet;
+
+const ADDRESSES: usize = 10_000;
+
+// Deterministic xorshift, good enough to scatter addresses
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn subnets(random: &mut Random, count: usize) -> Vec<Subnet> {
+    (0..count)
+        .map(|i| {
+            let addr = if i % 4 == 3 {
+                IpAddr::V6(Ipv6Addr::from(
+                    0xfd00_u128 << 112 | u128::from(random.next()) << 64,
+                ))
+            } else {
+                IpAddr::V4(Ipv4Addr::from(
+                    0x0a00_0000 | (random.next() as u32 & 0x00ff_ff00),
+                ))
+            };
+            let prefix_len = if addr.is_ipv4() { 24 } else { 64 };
+            Subnet::new(addr, prefix_len).unwrap()
+        })
+        .collect()
+}
+
+// Mostly remote addresses like in a real conntrack table, with a share of local ones
+fn addresses(random: &mut Random, subnets: &[Subnet]) -> Vec<IpAddr> {
+    (0..ADDRESSES)
+        .map(|i| match (i % 3, subnets[i % subnets.len()]) {
+            (0, Subnet::V4(network, _)) => IpAddr::V4(Ipv4Addr::from(
+                u32::from(network) | (random.next() as u32 & 0xff),
+            )),
+            (0, Subnet::V6(network, _)) => IpAddr::V6(Ipv6Addr::from(
+                u128::from(network) | u128::from(random.next()),
+            )),
+            _ if i % 5 == 0 => IpAddr::V6(Ipv6Addr::from(
+                u128::from(random.next()) << 64 | u128::from(random.next()),
+            )),
+            _ => IpAddr::V4(Ipv4Addr::from(random.next() as u32)),
+        })
+        .collect()
+}
+
+fn classify(c: &mut Criterion) {
+    let mut group = c.benchmark_group("classify");
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+    group.throughput(Throughput::Elements(ADDRESSES as u64));
+    for count in &[4, 64, 512] {
+        let subnets = subnets(&mut random, *count);
+        let addresses = addresses(&mut random, &subnets);
+        let networks = LocalNetworks::new(subnets.clone(), vec![]);
+
+        group.bench_with_input(
+            BenchmarkId::new("linear", count),
+            &addresses,
+            |b, addresses| {
+                b.iter(|| {
+                    addresses
+                        .iter()
+                        .filter(|addr| {
+                            subnets
+                                .iter()
+                                .any(|subnet| subnet.contains(black_box(addr)))
+                        })
+                        .count()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("trie", count),
+            &addresses,
+            |b, addresses| {
+                b.iter(|| {
+                    addresses
+                        .iter()
+                        .filter(|addr| networks.lookup(black_box(addr)).is_some())
+                        .count()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, classify);
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+use crate::config::HealthConfig;
+use crate::conntrack;
+use crate::device;
+use crate::leases;
+use crate::model::{Health, HealthStatus};
+use crate::Container;
+use actix_web::{error, web, Error, HttpResponse};
+use chrono::Utc;
+use futures::Future;
+
+fn query(container: &Container) -> impl Future<Item = Health, Error = Error> {
+    container
+        .conntrack
+        .send(conntrack::Health)
+        .join3(
+            container.device.send(device::Health),
+            container.leases.send(leases::Health),
+        )
+        .map_err(error::ErrorInternalServerError)
+        .map(|(conntrack, device, leases)| Health {
+            conntrack,
+            device,
+            leases,
+        })
+}
+
+fn respond(healthy: bool, health: Health) -> HttpResponse {
+    let status = HealthStatus { healthy, health };
+
+    if healthy {
+        HttpResponse::Ok().json(status)
+    } else {
+        HttpResponse::ServiceUnavailable().json(status)
+    }
+}
+
+pub fn health(container: web::Data<Container>) -> impl Future<Item = HttpResponse, Error = Error> {
+    query(&container).map(|health| HttpResponse::Ok().json(health))
+}
+
+pub fn healthz(
+    container: web::Data<Container>,
+    config: web::Data<HealthConfig>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let threshold = config.stale_after;
+
+    query(&container).map(move |health| {
+        let healthy = health.is_healthy(Utc::now().naive_utc(), threshold);
+
+        respond(healthy, health)
+    })
+}
+
+pub fn readyz(
+    container: web::Data<Container>,
+    config: web::Data<HealthConfig>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let threshold = config.stale_after;
+
+    query(&container).map(move |health| {
+        let ready = health.is_ready(Utc::now().naive_utc(), threshold);
+
+        respond(ready, health)
+    })
+}
@@ -0,0 +1,318 @@
+use crate::config::MetricsConfig;
+use crate::conntrack;
+use crate::device;
+use crate::leases::{self, Lease};
+use crate::model::{HostCounters, WanCounters};
+use crate::Container;
+use actix_web::{error, web, Error, HttpResponse};
+use futures::Future;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+
+struct Sample {
+    labels: Vec<(&'static str, String)>,
+    value: u64,
+}
+
+fn family(addr: &IpAddr) -> &'static str {
+    match addr {
+        IpAddr::V4(_) => "ipv4",
+        IpAddr::V6(_) => "ipv6",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[Sample]) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+    for sample in samples {
+        let labels = sample
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+            .collect::<Vec<String>>();
+        if labels.is_empty() {
+            writeln!(out, "{} {}", name, sample.value).unwrap();
+        } else {
+            writeln!(out, "{}{{{}}} {}", name, labels.join(","), sample.value).unwrap();
+        }
+    }
+}
+
+pub fn render(
+    wan: &WanCounters,
+    hosts: &[HostCounters],
+    leases: &HashMap<IpAddr, Lease>,
+    max_remote_series: usize,
+) -> String {
+    let mut out = String::new();
+    let wan_labels = |direction: &str| {
+        vec![
+            ("interface", wan.interface.clone()),
+            ("direction", direction.to_string()),
+        ]
+    };
+
+    write_metric(
+        &mut out,
+        "nftracker_wan_bytes_total",
+        "counter",
+        "Bytes transferred on the WAN interface.",
+        &[
+            Sample {
+                labels: wan_labels("in"),
+                value: wan.in_count.bytes,
+            },
+            Sample {
+                labels: wan_labels("out"),
+                value: wan.out_count.bytes,
+            },
+        ],
+    );
+    write_metric(
+        &mut out,
+        "nftracker_wan_packets_total",
+        "counter",
+        "Packets transferred on the WAN interface.",
+        &[
+            Sample {
+                labels: wan_labels("in"),
+                value: wan.in_count.packets,
+            },
+            Sample {
+                labels: wan_labels("out"),
+                value: wan.out_count.packets,
+            },
+        ],
+    );
+
+    let mut hosts = hosts.iter().collect::<Vec<&HostCounters>>();
+    hosts.sort_by_key(|host| host.addr);
+
+    let host_labels = |host: &HostCounters, direction: &str| {
+        vec![
+            ("host", host.addr.to_string()),
+            (
+                "hostname",
+                leases
+                    .get(&host.addr)
+                    .and_then(Lease::hostname)
+                    .unwrap_or("")
+                    .to_string(),
+            ),
+            ("direction", direction.to_string()),
+            ("family", family(&host.addr).to_string()),
+        ]
+    };
+    let mut host_bytes = Vec::with_capacity(2 * hosts.len());
+    let mut host_packets = Vec::with_capacity(2 * hosts.len());
+
+    for host in hosts.iter() {
+        for (direction, count) in &[("in", host.in_count), ("out", host.out_count)] {
+            host_bytes.push(Sample {
+                labels: host_labels(host, direction),
+                value: count.bytes,
+            });
+            host_packets.push(Sample {
+                labels: host_labels(host, direction),
+                value: count.packets,
+            });
+        }
+    }
+    write_metric(
+        &mut out,
+        "nftracker_host_bytes_total",
+        "counter",
+        "Bytes transferred by a local host.",
+        &host_bytes,
+    );
+    write_metric(
+        &mut out,
+        "nftracker_host_packets_total",
+        "counter",
+        "Packets transferred by a local host.",
+        &host_packets,
+    );
+
+    let mut remotes = hosts
+        .iter()
+        .flat_map(|host| host.remotes.iter().map(move |remote| (*host, remote)))
+        .collect::<Vec<_>>();
+    let dropped = remotes.len().saturating_sub(max_remote_series);
+
+    // Only the busiest remotes are exported to keep the number of series bounded
+    remotes.sort_by_key(|(_, remote)| Reverse(remote.in_count.bytes + remote.out_count.bytes));
+    remotes.truncate(max_remote_series);
+    remotes.sort_by_key(|(host, remote)| (host.addr, remote.addr));
+
+    let remote_labels = |host: &HostCounters, remote: IpAddr, direction: &str| {
+        vec![
+            ("host", host.addr.to_string()),
+            ("remote", remote.to_string()),
+            ("direction", direction.to_string()),
+            ("family", family(&host.addr).to_string()),
+        ]
+    };
+    let mut remote_bytes = Vec::with_capacity(2 * remotes.len());
+    let mut remote_packets = Vec::with_capacity(2 * remotes.len());
+
+    for (host, remote) in remotes {
+        for (direction, count) in &[("in", remote.in_count), ("out", remote.out_count)] {
+            remote_bytes.push(Sample {
+                labels: remote_labels(host, remote.addr, direction),
+                value: count.bytes,
+            });
+            remote_packets.push(Sample {
+                labels: remote_labels(host, remote.addr, direction),
+                value: count.packets,
+            });
+        }
+    }
+    write_metric(
+        &mut out,
+        "nftracker_remote_bytes_total",
+        "counter",
+        "Bytes transferred between a local host and a remote.",
+        &remote_bytes,
+    );
+    write_metric(
+        &mut out,
+        "nftracker_remote_packets_total",
+        "counter",
+        "Packets transferred between a local host and a remote.",
+        &remote_packets,
+    );
+    write_metric(
+        &mut out,
+        "nftracker_remote_series_dropped",
+        "gauge",
+        "Remote series omitted due to the max_remote_series limit.",
+        &[Sample {
+            labels: vec![],
+            value: dropped as u64,
+        }],
+    );
+
+    out
+}
+
+pub fn metrics(
+    container: web::Data<Container>,
+    config: web::Data<MetricsConfig>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    container
+        .device
+        .send(device::Counters)
+        .join3(
+            container.conntrack.send(conntrack::Counters),
+            container.leases.send(leases::Snapshot),
+        )
+        .map_err(error::ErrorInternalServerError)
+        .map(move |(wan, hosts, leases)| {
+            HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4")
+                .body(render(&wan, &hosts, &leases, config.max_remote_series))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::TrafficCounter;
+    use crate::model::RemoteCounters;
+    use spectral::prelude::*;
+
+    fn counter(bytes: u64) -> TrafficCounter {
+        TrafficCounter {
+            bytes,
+            packets: bytes / 100,
+        }
+    }
+
+    fn remote(addr: &str, bytes: u64) -> RemoteCounters {
+        RemoteCounters {
+            addr: addr.parse().unwrap(),
+            in_count: counter(bytes),
+            out_count: counter(bytes / 10),
+        }
+    }
+
+    fn fixture() -> (WanCounters, Vec<HostCounters>, HashMap<IpAddr, Lease>) {
+        let brick: IpAddr = "192.168.3.86".parse().unwrap();
+        let wan = WanCounters {
+            interface: "eth0".to_string(),
+            in_count: counter(10_000),
+            out_count: counter(2_000),
+        };
+        let hosts = vec![
+            HostCounters {
+                addr: brick,
+                in_count: counter(3_000),
+                out_count: counter(300),
+                remotes: vec![remote("1.2.3.4", 1_000), remote("1.2.3.5", 2_000)],
+            },
+            HostCounters {
+                addr: "1234::28a".parse().unwrap(),
+                in_count: counter(500),
+                out_count: counter(50),
+                remotes: vec![remote("2345::1", 500)],
+            },
+        ];
+        let mut leases = HashMap::new();
+        leases.insert(
+            brick,
+            Lease {
+                name: "br\"ick".to_string(),
+                addr: brick,
+                mac: None,
+                client_id: "01:24:5e:be:12:34:56".to_string(),
+            },
+        );
+
+        (wan, hosts, leases)
+    }
+
+    #[test]
+    fn test_render_counters() {
+        let (wan, hosts, leases) = fixture();
+        let rendered = render(&wan, &hosts, &leases, 100);
+
+        let lines = rendered.lines().collect::<Vec<&str>>();
+
+        for expected in &[
+            "# TYPE nftracker_wan_bytes_total counter",
+            r#"nftracker_wan_bytes_total{interface="eth0",direction="in"} 10000"#,
+            r#"nftracker_wan_packets_total{interface="eth0",direction="out"} 20"#,
+            r#"nftracker_host_bytes_total{host="192.168.3.86",hostname="br\"ick",direction="in",family="ipv4"} 3000"#,
+            r#"nftracker_host_bytes_total{host="1234::28a",hostname="",direction="out",family="ipv6"} 50"#,
+            r#"nftracker_remote_bytes_total{host="192.168.3.86",remote="1.2.3.5",direction="in",family="ipv4"} 2000"#,
+            "nftracker_remote_series_dropped 0",
+        ] {
+            assert_that(&lines).contains(expected);
+        }
+    }
+
+    #[test]
+    fn test_render_remote_series_cap() {
+        let (wan, hosts, leases) = fixture();
+        let rendered = render(&wan, &hosts, &leases, 1);
+        let remote_lines = rendered
+            .lines()
+            .filter(|line| line.starts_with("nftracker_remote_bytes_total{"))
+            .collect::<Vec<&str>>();
+
+        assert_that(&remote_lines).is_equal_to(vec![
+            r#"nftracker_remote_bytes_total{host="192.168.3.86",remote="1.2.3.5",direction="in",family="ipv4"} 2000"#,
+            r#"nftracker_remote_bytes_total{host="192.168.3.86",remote="1.2.3.5",direction="out",family="ipv4"} 200"#,
+        ]);
+        assert_that(&rendered.contains("nftracker_remote_series_dropped 2\n")).is_true();
+    }
+}
@@ -0,0 +1,346 @@
+mod health;
+mod metrics;
+mod stream;
+
+pub use stream::Broadcaster;
+
+use crate::common;
+use crate::conntrack;
+use crate::device;
+use crate::leases::{self, Lease};
+use crate::minirrd::{self, Aggregator, Archive};
+use crate::model::{Device, LeaseInfo, Window, WithLease};
+use crate::Container;
+use actix_web::{error, web, Error, HttpResponse};
+use chrono::{NaiveDateTime, Utc};
+use futures::future::{self, Either};
+use futures::Future;
+use serde_derive::Deserialize;
+use std::cmp::Reverse;
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/v1")
+            .route("/wan", web::get().to_async(wan))
+            .route("/hosts", web::get().to_async(hosts))
+            .route("/hosts/{ip}/remotes", web::get().to_async(remotes))
+            .route("/hosts/{ip}/volume", web::get().to_async(volume))
+            .route("/groups", web::get().to_async(groups))
+            .route("/top", web::get().to_async(top))
+            .route("/devices", web::get().to_async(devices))
+            .route("/leases", web::get().to_async(leases))
+            .route("/health", web::get().to_async(health::health))
+            .route("/stream", web::get().to_async(stream::stream)),
+    )
+    .route("/metrics", web::get().to_async(metrics::metrics))
+    .route("/healthz", web::get().to_async(health::healthz))
+    .route("/readyz", web::get().to_async(health::readyz));
+}
+
+fn parse_duration(
+    name: &str,
+    value: Option<&String>,
+    default: Duration,
+) -> Result<chrono::Duration, HttpResponse> {
+    match value {
+        Some(value) => humantime::parse_duration(value).map_err(|err| err.to_string()),
+        None => Ok(default),
+    }
+    .and_then(|duration| chrono::Duration::from_std(duration).map_err(|err| err.to_string()))
+    .map_err(|err| HttpResponse::BadRequest().body(format!("Invalid {}: {}", name, err)))
+}
+
+fn before_now(name: &str, duration: chrono::Duration) -> Result<NaiveDateTime, HttpResponse> {
+    Utc::now()
+        .naive_utc()
+        .checked_sub_signed(duration)
+        .ok_or_else(|| HttpResponse::BadRequest().body(format!("Invalid {}: out of range", name)))
+}
+
+fn parse_aggregator(value: Option<&String>) -> Result<Aggregator, HttpResponse> {
+    match value.map(String::as_str) {
+        None | Some("sum") => Ok(Aggregator::DeltaSum),
+        Some("max") => Ok(Aggregator::Max),
+        Some("mean") => Ok(Aggregator::Mean),
+        Some(value) => Err(HttpResponse::BadRequest().body(format!(
+            "Invalid aggregate: {} is none of sum, max or mean",
+            value
+        ))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WindowQuery {
+    since: Option<String>,
+    step: Option<String>,
+    aggregate: Option<String>,
+}
+
+impl WindowQuery {
+    /// Without `since`, `step` and `aggregate` the raw rates are returned. There is no data
+    /// beyond the coarsest archive, so `since` is limited to its `retain`.
+    fn window(&self, archives: &[Archive]) -> Result<Option<Window>, HttpResponse> {
+        if self.since.is_none() && self.step.is_none() && self.aggregate.is_none() {
+            return Ok(None);
+        }
+        let since = parse_duration("since", self.since.as_ref(), Duration::from_secs(300))?;
+        let step = parse_duration("step", self.step.as_ref(), common::RESOLUTION)?;
+        let aggregator = parse_aggregator(self.aggregate.as_ref())?;
+        let since = archives
+            .iter()
+            .filter_map(|archive| chrono::Duration::from_std(archive.retain).ok())
+            .max()
+            .map_or(since, |retain| since.min(retain));
+
+        if step.to_std().map_or(true, |step| step < common::RESOLUTION) {
+            return Err(HttpResponse::BadRequest().body(format!(
+                "Invalid step: below the resolution of {}",
+                humantime::format_duration(common::RESOLUTION)
+            )));
+        }
+        if since.num_milliseconds() / step.num_milliseconds() >= minirrd::MAX_BUCKETS as i64 {
+            return Err(HttpResponse::BadRequest().body(format!(
+                "Invalid step: more than {} steps",
+                minirrd::MAX_BUCKETS
+            )));
+        }
+
+        Ok(Some(Window {
+            from: before_now("since", since)?,
+            to: Utc::now().naive_utc(),
+            step,
+            aggregator,
+        }))
+    }
+}
+
+fn wan(
+    container: web::Data<Container>,
+    archives: web::Data<Vec<Archive>>,
+    query: web::Query<WindowQuery>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let window = match query.window(&archives) {
+        Ok(window) => window,
+        Err(response) => return Either::A(future::ok(response)),
+    };
+
+    Either::B(
+        container
+            .device
+            .send(device::Snapshot(window))
+            .map_err(error::ErrorInternalServerError)
+            .map(|wan| HttpResponse::Ok().json(wan)),
+    )
+}
+
+fn hosts(container: web::Data<Container>) -> impl Future<Item = HttpResponse, Error = Error> {
+    container
+        .conntrack
+        .send(conntrack::Hosts)
+        .join(container.leases.send(leases::Snapshot))
+        .map_err(error::ErrorInternalServerError)
+        .map(|(mut hosts, leases)| {
+            hosts.sort_by_key(|host| host.addr);
+            HttpResponse::Ok().json(
+                hosts
+                    .into_iter()
+                    .map(|host| WithLease {
+                        lease: LeaseInfo::lookup(&leases, &host.addr),
+                        host,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+}
+
+fn groups(container: web::Data<Container>) -> impl Future<Item = HttpResponse, Error = Error> {
+    container
+        .conntrack
+        .send(conntrack::Groups)
+        .map_err(error::ErrorInternalServerError)
+        .map(|groups| HttpResponse::Ok().json(groups))
+}
+
+fn remotes(
+    container: web::Data<Container>,
+    archives: web::Data<Vec<Archive>>,
+    ip: web::Path<IpAddr>,
+    query: web::Query<WindowQuery>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let window = match query.window(&archives) {
+        Ok(window) => window,
+        Err(response) => return Either::A(future::ok(response)),
+    };
+
+    Either::B(
+        container
+            .conntrack
+            .send(conntrack::Remotes(ip.into_inner(), window))
+            .map_err(error::ErrorInternalServerError)
+            .map(|remotes| match remotes {
+                Some(mut remotes) => {
+                    remotes.sort_by_key(|remote| remote.addr);
+                    HttpResponse::Ok().json(remotes)
+                }
+                None => HttpResponse::NotFound().finish(),
+            }),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct VolumeQuery {
+    since: Option<String>,
+}
+
+fn volume(
+    container: web::Data<Container>,
+    ip: web::Path<IpAddr>,
+    query: web::Query<VolumeQuery>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let since = match parse_duration("since", query.since.as_ref(), Duration::from_secs(86_400))
+        .and_then(|since| before_now("since", since))
+    {
+        Ok(since) => since,
+        Err(response) => return Either::A(future::ok(response)),
+    };
+
+    Either::B(
+        container
+            .conntrack
+            .send(conntrack::Volume(ip.into_inner(), since))
+            .map_err(error::ErrorInternalServerError)
+            .map(|volume| match volume {
+                Some(volume) => HttpResponse::Ok().json(volume),
+                None => HttpResponse::NotFound().finish(),
+            }),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct TopQuery {
+    limit: Option<usize>,
+}
+
+fn top(
+    container: web::Data<Container>,
+    query: web::Query<TopQuery>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let limit = query.limit.unwrap_or(10);
+
+    container
+        .conntrack
+        .send(conntrack::Snapshot)
+        .join(container.leases.send(leases::Snapshot))
+        .map_err(error::ErrorInternalServerError)
+        .map(move |(mut hosts, leases)| {
+            hosts.sort_by_key(|host| Reverse(host.current_bytes_per_sec()));
+            hosts.truncate(limit);
+            for host in hosts.iter_mut() {
+                host.remotes.sort_by_key(|remote| {
+                    Reverse(remote.in_rate.bytes_per_sec() + remote.out_rate.bytes_per_sec())
+                });
+            }
+            HttpResponse::Ok().json(
+                hosts
+                    .into_iter()
+                    .map(|host| WithLease {
+                        lease: LeaseInfo::lookup(&leases, &host.addr),
+                        host,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+}
+
+fn devices(container: web::Data<Container>) -> impl Future<Item = HttpResponse, Error = Error> {
+    container
+        .conntrack
+        .send(conntrack::Snapshot)
+        .join(container.leases.send(leases::Snapshot))
+        .map_err(error::ErrorInternalServerError)
+        .map(|(hosts, leases)| HttpResponse::Ok().json(Device::group(hosts, &leases)))
+}
+
+fn leases(container: web::Data<Container>) -> impl Future<Item = HttpResponse, Error = Error> {
+    container
+        .leases
+        .send(leases::Snapshot)
+        .map_err(error::ErrorInternalServerError)
+        .map(|leases| {
+            let mut leases = leases.into_values().collect::<Vec<Lease>>();
+            leases.sort_by_key(|lease| lease.addr);
+            HttpResponse::Ok().json(leases)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    fn window(since: Option<&str>, step: Option<&str>) -> Result<Option<Window>, HttpResponse> {
+        aggregated(since, step, None)
+    }
+
+    fn aggregated(
+        since: Option<&str>,
+        step: Option<&str>,
+        aggregate: Option<&str>,
+    ) -> Result<Option<Window>, HttpResponse> {
+        let archives = vec![
+            Archive {
+                resolution: common::RESOLUTION,
+                retain: Duration::from_secs(300),
+            },
+            Archive {
+                resolution: Duration::from_secs(60),
+                retain: Duration::from_secs(86_400),
+            },
+        ];
+        WindowQuery {
+            since: since.map(str::to_string),
+            step: step.map(str::to_string),
+            aggregate: aggregate.map(str::to_string),
+        }
+        .window(&archives)
+    }
+
+    #[test]
+    fn test_window() {
+        assert_that(&window(None, None).unwrap()).is_none();
+
+        let window15m = window(Some("15m"), Some("10s")).unwrap().unwrap();
+        assert_that(&(window15m.to - window15m.from).num_minutes()).is_equal_to(15);
+        assert_that(&window15m.step).is_equal_to(chrono::Duration::seconds(10));
+
+        // Nothing is retained for longer than a day
+        let window10y = window(Some("10y"), Some("1m")).unwrap().unwrap();
+        assert_that(&(window10y.to - window10y.from).num_hours()).is_equal_to(24);
+
+        assert_that(&window15m.aggregator).is_equal_to(Aggregator::DeltaSum);
+        let peak = aggregated(None, None, Some("max")).unwrap().unwrap();
+        assert_that(&peak.aggregator).is_equal_to(Aggregator::Max);
+        assert_that(&(peak.to - peak.from).num_minutes()).is_equal_to(5);
+        let mean = aggregated(Some("1h"), Some("1m"), Some("mean"))
+            .unwrap()
+            .unwrap();
+        assert_that(&mean.aggregator).is_equal_to(Aggregator::Mean);
+    }
+
+    #[test]
+    fn test_window_invalid() {
+        let status = |since, step| window(since, step).unwrap_err().status().as_u16();
+
+        assert_that(&status(None, Some("0s"))).is_equal_to(400);
+        assert_that(&status(None, Some("500us"))).is_equal_to(400);
+        assert_that(&status(Some("10y"), Some("1ms"))).is_equal_to(400);
+        assert_that(&status(Some("10y"), Some("1s"))).is_equal_to(400);
+        assert_that(&status(Some("99999999999y"), None)).is_equal_to(400);
+        assert_that(&status(Some("soon"), None)).is_equal_to(400);
+
+        let status = aggregated(None, None, Some("median")).unwrap_err().status();
+        assert_that(&status.as_u16()).is_equal_to(400);
+    }
+}
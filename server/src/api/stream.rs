@@ -0,0 +1,229 @@
+use crate::common::Subnet;
+use crate::model::RatesUpdate;
+use crate::Container;
+use actix::{Actor, Context, Handler, Message};
+use actix_web::web::Bytes;
+use actix_web::{error, web, Error, HttpResponse};
+use futures::sync::mpsc;
+use futures::{Future, Stream};
+use log::error;
+use serde_derive::Deserialize;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const CLIENT_BUFFER: usize = 16;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamFilter {
+    host: Option<IpAddr>,
+    subnet: Option<Subnet>,
+}
+
+impl StreamFilter {
+    fn matches(&self, addr: &IpAddr) -> bool {
+        self.host.is_none_or(|host| host == *addr)
+            && self
+                .subnet
+                .as_ref()
+                .is_none_or(|subnet| subnet.contains(addr))
+    }
+
+    fn apply(&self, update: &RatesUpdate) -> RatesUpdate {
+        match update {
+            RatesUpdate::Hosts { timestamp, hosts } => RatesUpdate::Hosts {
+                timestamp: *timestamp,
+                hosts: hosts
+                    .iter()
+                    .filter(|host| self.matches(&host.addr))
+                    .cloned()
+                    .collect(),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+fn encode_event(update: &RatesUpdate) -> Option<Bytes> {
+    let event = match update {
+        RatesUpdate::Wan { .. } => "wan",
+        RatesUpdate::Hosts { .. } => "hosts",
+    };
+
+    match serde_json::to_string(update) {
+        Ok(data) => Some(Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))),
+        Err(error) => {
+            error!("Encode update failed: {}", error);
+            None
+        }
+    }
+}
+
+struct Client {
+    sender: mpsc::Sender<Bytes>,
+    filter: StreamFilter,
+}
+
+#[derive(Default)]
+pub struct Broadcaster {
+    clients: Vec<Client>,
+    // Shared with the collectors, so they do not build updates nobody receives
+    active: Arc<AtomicBool>,
+}
+
+impl Broadcaster {
+    pub fn active(&self) -> Arc<AtomicBool> {
+        self.active.clone()
+    }
+
+    fn update_active(&self) {
+        self.active
+            .store(!self.clients.is_empty(), Ordering::Relaxed);
+    }
+}
+
+#[derive(Message)]
+struct Connect(Client);
+
+impl Handler<Connect> for Broadcaster {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connect, _: &mut Context<Broadcaster>) {
+        self.clients.push(msg.0);
+        self.update_active();
+    }
+}
+
+impl Handler<RatesUpdate> for Broadcaster {
+    type Result = ();
+
+    fn handle(&mut self, update: RatesUpdate, _: &mut Context<Broadcaster>) {
+        self.clients.retain_mut(|client| {
+            let event = match encode_event(&client.filter.apply(&update)) {
+                Some(event) => event,
+                None => return true,
+            };
+            // A full buffer means a slow client, which just misses this update
+            match client.sender.try_send(event) {
+                Ok(_) => true,
+                Err(error) => !error.is_disconnected(),
+            }
+        });
+        self.update_active();
+    }
+}
+
+impl Actor for Broadcaster {
+    type Context = Context<Self>;
+}
+
+pub fn stream(
+    container: web::Data<Container>,
+    filter: web::Query<StreamFilter>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let (sender, receiver) = mpsc::channel(CLIENT_BUFFER);
+
+    container
+        .broadcaster
+        .send(Connect(Client {
+            sender,
+            filter: filter.into_inner(),
+        }))
+        .map_err(error::ErrorInternalServerError)
+        .map(|_| {
+            HttpResponse::Ok()
+                .content_type("text/event-stream")
+                .header("Cache-Control", "no-cache")
+                .streaming(receiver.map_err(|_| error::ErrorInternalServerError("Stream closed")))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::HostRate;
+    use actix::System;
+    use chrono::Utc;
+    use spectral::prelude::*;
+
+    fn hosts_update(addrs: &[&str]) -> RatesUpdate {
+        RatesUpdate::Hosts {
+            timestamp: Utc::now().naive_utc(),
+            hosts: addrs
+                .iter()
+                .map(|addr| HostRate {
+                    addr: addr.parse().unwrap(),
+                    in_rate: Default::default(),
+                    out_rate: Default::default(),
+                })
+                .collect(),
+        }
+    }
+
+    fn filtered_addrs(filter: &StreamFilter, update: &RatesUpdate) -> Vec<String> {
+        match filter.apply(update) {
+            RatesUpdate::Hosts { hosts, .. } => {
+                hosts.iter().map(|host| host.addr.to_string()).collect()
+            }
+            _ => panic!("Expected hosts update"),
+        }
+    }
+
+    #[test]
+    fn test_filter_hosts() {
+        let update = hosts_update(&["192.168.3.86", "192.168.4.2", "1234::28a"]);
+
+        assert_that(&filtered_addrs(&StreamFilter::default(), &update).len()).is_equal_to(3);
+        assert_that(&filtered_addrs(
+            &StreamFilter {
+                host: Some("1234::28a".parse().unwrap()),
+                subnet: None,
+            },
+            &update,
+        ))
+        .is_equal_to(vec!["1234::28a".to_string()]);
+        assert_that(&filtered_addrs(
+            &StreamFilter {
+                host: None,
+                subnet: Some("192.168.4.0/24".parse().unwrap()),
+            },
+            &update,
+        ))
+        .is_equal_to(vec!["192.168.4.2".to_string()]);
+    }
+
+    #[test]
+    fn test_encode_event() {
+        let event = encode_event(&hosts_update(&["192.168.3.86"])).unwrap();
+        let event = String::from_utf8(event.to_vec()).unwrap();
+
+        assert_that(&event.starts_with("event: hosts\ndata: {\"type\":\"hosts\"")).is_true();
+        assert_that(&event.ends_with("\n\n")).is_true();
+    }
+
+    #[test]
+    fn test_active_while_connected() {
+        let mut sys = System::new("test");
+        let broadcaster = Broadcaster::default();
+        let active = broadcaster.active();
+        let broadcaster = broadcaster.start();
+        let (sender, receiver) = mpsc::channel(CLIENT_BUFFER);
+
+        assert_that(&active.load(Ordering::Relaxed)).is_false();
+
+        sys.block_on(broadcaster.send(Connect(Client {
+            sender,
+            filter: StreamFilter::default(),
+        })))
+        .unwrap();
+
+        assert_that(&active.load(Ordering::Relaxed)).is_true();
+
+        // The disconnect is noticed with the next update
+        drop(receiver);
+        sys.block_on(broadcaster.send(hosts_update(&["192.168.3.86"])))
+            .unwrap();
+
+        assert_that(&active.load(Ordering::Relaxed)).is_false();
+    }
+}
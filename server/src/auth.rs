@@ -0,0 +1,297 @@
+use crate::config::{AuthConfig, Scope};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::http::{header, Method};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, Either, FutureResult};
+use futures::Poll;
+use log::warn;
+use ring::digest::SHA256;
+use ring::hmac::{self, SigningKey};
+use ring::rand::SystemRandom;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const MAX_VERIFIED: usize = 64;
+// Probes of watchdogs and load balancers
+const PUBLIC_PATHS: &[&str] = &["/healthz", "/readyz"];
+
+pub struct Authenticator {
+    config: AuthConfig,
+    // Password hashes are deliberately slow to check, so successful logins are remembered.
+    // The cache is keyed by an HMAC with a per-process key, so no credentials are kept.
+    cache_key: SigningKey,
+    verified: RefCell<HashMap<Vec<u8>, Scope>>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> Authenticator {
+        Authenticator {
+            config,
+            cache_key: SigningKey::generate(&SHA256, &SystemRandom::new())
+                .expect("Generate authentication cache key"),
+            verified: Default::default(),
+        }
+    }
+
+    pub fn authenticate(&self, authorization: &str) -> Option<Scope> {
+        let cache_key = hmac::sign(&self.cache_key, authorization.as_bytes())
+            .as_ref()
+            .to_vec();
+
+        if let Some(scope) = self.verified.borrow().get(&cache_key) {
+            return Some(*scope);
+        }
+        let scope = if let Some(token) = authorization.strip_prefix("Bearer ") {
+            self.check_token(token.trim())
+        } else if let Some(credentials) = authorization.strip_prefix("Basic ") {
+            self.check_basic(credentials.trim())
+        } else {
+            None
+        }?;
+        let mut verified = self.verified.borrow_mut();
+
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+        verified.insert(cache_key, scope);
+
+        Some(scope)
+    }
+
+    fn check_token(&self, token: &str) -> Option<Scope> {
+        self.config
+            .tokens
+            .iter()
+            .find(|candidate| constant_time_eq(candidate.token.as_bytes(), token.as_bytes()))
+            .map(|candidate| candidate.scope)
+    }
+
+    fn check_basic(&self, credentials: &str) -> Option<Scope> {
+        let decoded = String::from_utf8(base64::decode(credentials).ok()?).ok()?;
+        let (name, password) = decoded.split_once(':')?;
+        let user = self.config.users.iter().find(|user| user.name == name)?;
+
+        if verify_password(password, &user.password_hash) {
+            Some(user.scope)
+        } else {
+            None
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+    } else if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else {
+        warn!("Unsupported password hash (neither bcrypt nor argon2)");
+        false
+    }
+}
+
+fn required_scope(method: &Method) -> Scope {
+    if method == Method::GET || method == Method::HEAD {
+        Scope::Read
+    } else {
+        Scope::Admin
+    }
+}
+
+pub struct Auth {
+    config: AuthConfig,
+}
+
+impl Auth {
+    pub fn new(config: AuthConfig) -> Auth {
+        Auth { config }
+    }
+}
+
+impl<S, B> Transform<S> for Auth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthMiddleware {
+            service,
+            authenticator: Rc::new(Authenticator::new(self.config.clone())),
+        })
+    }
+}
+
+pub struct AuthMiddleware<S> {
+    service: S,
+    authenticator: Rc<Authenticator>,
+}
+
+impl<S, B> Service for AuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, FutureResult<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if !self.authenticator.config.is_enabled() || PUBLIC_PATHS.contains(&req.path()) {
+            return Either::A(self.service.call(req));
+        }
+        let scope = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|authorization| self.authenticator.authenticate(authorization));
+
+        match scope {
+            Some(scope) if scope >= required_scope(req.method()) => {
+                Either::A(self.service.call(req))
+            }
+            Some(_) => Either::B(ok(req.error_response(InternalError::from_response(
+                "Insufficient scope",
+                HttpResponse::Forbidden().finish(),
+            )))),
+            None => Either::B(ok(req.error_response(InternalError::from_response(
+                "Unauthorized",
+                HttpResponse::Unauthorized()
+                    .header(header::WWW_AUTHENTICATE, "Basic realm=\"nftracker\"")
+                    .finish(),
+            )))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TokenConfig, UserConfig};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+    use spectral::prelude::*;
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            tokens: vec![TokenConfig {
+                token: "reader-token".to_string(),
+                scope: Scope::Read,
+            }],
+            users: vec![
+                UserConfig {
+                    name: "admin".to_string(),
+                    password_hash: bcrypt::hash("bcrypt-secret", 4).unwrap(),
+                    scope: Scope::Admin,
+                },
+                UserConfig {
+                    name: "viewer".to_string(),
+                    password_hash: argon2::hash_encoded(
+                        b"argon2-secret",
+                        b"some-salt",
+                        &argon2::Config::default(),
+                    )
+                    .unwrap(),
+                    scope: Scope::Read,
+                },
+            ],
+        }
+    }
+
+    fn basic(name: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            base64::encode(&format!("{}:{}", name, password))
+        )
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let authenticator = Authenticator::new(config());
+
+        assert_that(&authenticator.authenticate("Bearer reader-token")).contains_value(Scope::Read);
+        assert_that(&authenticator.authenticate("Bearer other-token")).is_none();
+        assert_that(&authenticator.authenticate(&basic("admin", "bcrypt-secret")))
+            .contains_value(Scope::Admin);
+        assert_that(&authenticator.authenticate(&basic("admin", "wrong"))).is_none();
+        assert_that(&authenticator.authenticate(&basic("viewer", "argon2-secret")))
+            .contains_value(Scope::Read);
+        assert_that(&authenticator.authenticate(&basic("viewer", "bcrypt-secret"))).is_none();
+        assert_that(&authenticator.authenticate(&basic("nobody", "argon2-secret"))).is_none();
+        assert_that(&authenticator.authenticate("Digest whatever")).is_none();
+        // Cached logins are only known by their HMAC
+        assert_that(&authenticator.authenticate(&basic("admin", "bcrypt-secret")))
+            .contains_value(Scope::Admin);
+        assert_that(&authenticator.verified.borrow().len()).is_equal_to(3);
+        assert_that(
+            &authenticator
+                .verified
+                .borrow()
+                .keys()
+                .all(|key| key.len() == SHA256.output_len),
+        )
+        .is_true();
+    }
+
+    #[test]
+    fn test_middleware() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Auth::new(config()))
+                .route("/", web::get().to(HttpResponse::Ok))
+                .route("/", web::post().to(HttpResponse::Ok))
+                .route("/healthz", web::get().to(HttpResponse::Ok)),
+        );
+        let mut status =
+            |req: test::TestRequest| test::call_service(&mut app, req.to_request()).status();
+
+        assert_that(&status(test::TestRequest::get().uri("/")))
+            .is_equal_to(StatusCode::UNAUTHORIZED);
+        assert_that(&status(
+            test::TestRequest::get()
+                .uri("/")
+                .header(header::AUTHORIZATION, "Bearer reader-token"),
+        ))
+        .is_equal_to(StatusCode::OK);
+        assert_that(&status(
+            test::TestRequest::post()
+                .uri("/")
+                .header(header::AUTHORIZATION, "Bearer reader-token"),
+        ))
+        .is_equal_to(StatusCode::FORBIDDEN);
+        assert_that(&status(
+            test::TestRequest::post()
+                .uri("/")
+                .header(header::AUTHORIZATION, basic("admin", "bcrypt-secret")),
+        ))
+        .is_equal_to(StatusCode::OK);
+        assert_that(&status(test::TestRequest::get().uri("/healthz"))).is_equal_to(StatusCode::OK);
+    }
+
+    #[test]
+    fn test_middleware_disabled() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Auth::new(AuthConfig::default()))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        );
+        let response = test::call_service(&mut app, test::TestRequest::get().uri("/").to_request());
+
+        assert_that(&response.status()).is_equal_to(StatusCode::OK);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::minirrd::{invalid_data, Persist};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"NFTR";
+const VERSION: u8 = 3;
+
+/// Write a checkpoint file. The data goes to a temporary file first, so a crash while
+/// writing never destroys the previous checkpoint.
+pub fn save<P, F>(path: P, write: F) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&tmp_path)?);
+
+    out.write_all(MAGIC)?;
+    VERSION.write_to(&mut out)?;
+    write(&mut out)?;
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    drop(out);
+
+    fs::rename(tmp_path, path)
+}
+
+/// Read a checkpoint file, `None` if there is none (yet)
+pub fn load<P, T, F>(path: P, read: F) -> io::Result<Option<T>>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufReader<File>) -> io::Result<T>,
+{
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    let mut input = BufReader::new(file);
+    let mut magic = [0u8; 4];
+
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a checkpoint file"));
+    }
+    if u8::read_from(&mut input)? != VERSION {
+        return Err(invalid_data("unsupported checkpoint version"));
+    }
+
+    read(&mut input).map(Some)
+}
+
+impl Persist for IpAddr {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match self {
+            IpAddr::V4(addr) => {
+                4u8.write_to(out)?;
+                out.write_all(&addr.octets())
+            }
+            IpAddr::V6(addr) => {
+                6u8.write_to(out)?;
+                out.write_all(&addr.octets())
+            }
+        }
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        match u8::read_from(input)? {
+            4 => {
+                let mut octets = [0u8; 4];
+                input.read_exact(&mut octets)?;
+                Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+            }
+            6 => {
+                let mut octets = [0u8; 16];
+                input.read_exact(&mut octets)?;
+                Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => Err(invalid_data("invalid address family")),
+        }
+    }
+}
+
+/// Names are short, anything longer is a corrupt checkpoint
+const MAX_STRING_LEN: u64 = 1024;
+
+impl Persist for String {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (self.len() as u64).write_to(out)?;
+        out.write_all(self.as_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let len = u64::read_from(input)?;
+        if len > MAX_STRING_LEN {
+            return Err(invalid_data("string too long"));
+        }
+        let mut bytes = vec![0u8; len as usize];
+
+        input.read_exact(&mut bytes)?;
+        String::from_utf8(bytes).map_err(|_| invalid_data("invalid string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+    use std::env;
+
+    #[test]
+    fn test_save_load() {
+        let path = env::temp_dir().join(format!("nftracker-checkpoint-{}.rrd", std::process::id()));
+        let addrs: Vec<IpAddr> = vec!["192.168.3.10".parse().unwrap(), "1234::1".parse().unwrap()];
+
+        assert_that(&load(&path, IpAddr::read_from).unwrap()).is_none();
+
+        save(&path, |out| {
+            for addr in &addrs {
+                addr.write_to(out)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let restored = load(&path, |input| {
+            Ok(vec![IpAddr::read_from(input)?, IpAddr::read_from(input)?])
+        })
+        .unwrap();
+
+        assert_that(&restored).contains_value(addrs);
+
+        fs::write(&path, b"garbage").unwrap();
+        assert_that(&load(&path, IpAddr::read_from).is_err()).is_true();
+
+        fs::remove_file(&path).unwrap();
+    }
+}
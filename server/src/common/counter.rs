@@ -1,43 +1,124 @@
 use super::TrafficRate;
-use crate::minirrd::{RRDEntry, RRD};
+use crate::minirrd::{Aggregatable, Aggregator, Archive, MultiRRD, Persist, RRDEntry, RRD};
 use chrono::{NaiveDateTime, Utc};
 use itertools::Itertools;
+use serde_derive::Serialize;
+use std::io::{self, Read, Write};
+use std::ops;
 use std::time::Duration;
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Serialize)]
 pub struct TrafficCounter {
     pub bytes: u64,
     pub packets: u64,
 }
 
+// Counters only ever grow, unless they have been reset (recycled conntrack entry, 32-bit
+// wrap in /proc/net/dev, reboot). A lower value than before starts a new epoch counting from 0.
+
+fn epoch_delta(current: u64, previous: u64) -> u64 {
+    if previous <= current {
+        current - previous
+    } else {
+        current
+    }
+}
+
+fn epoch_interpolate(current: u64, previous: u64, index: u64, steps: u64) -> u64 {
+    if previous <= current {
+        previous + (current - previous) * index / steps
+    } else {
+        current * index / steps
+    }
+}
+
 impl RRDEntry for TrafficCounter {
-    fn combine(self, other: &Self) -> Self {
+    fn combine(self, _other: &Self) -> Self {
+        // The latest reading wins, even if it is lower (i.e. a new epoch)
+        self
+    }
+
+    fn interpolate(&self, previous: &Self, index: u64, steps: u64) -> Self {
+        TrafficCounter {
+            bytes: epoch_interpolate(self.bytes, previous.bytes, index, steps),
+            packets: epoch_interpolate(self.packets, previous.packets, index, steps),
+        }
+    }
+
+    fn consolidate(self, _older: &Self) -> Self {
+        // Counters are cumulative, so the latest reading covers the whole slot
+        self
+    }
+}
+
+impl Aggregatable for TrafficCounter {
+    fn max(&self, other: &Self) -> Self {
         TrafficCounter {
-            packets: self.packets.max(other.packets),
             bytes: self.bytes.max(other.bytes),
+            packets: self.packets.max(other.packets),
         }
     }
 
-    fn interpolate(&self, previous: &Self, index: u64, steps: u64) -> Self {
+    fn sum(&self, other: &Self) -> Self {
+        TrafficCounter {
+            bytes: self.bytes + other.bytes,
+            packets: self.packets + other.packets,
+        }
+    }
+
+    fn divide(&self, count: u64) -> Self {
+        TrafficCounter {
+            bytes: self.bytes / count,
+            packets: self.packets / count,
+        }
+    }
+
+    fn delta(&self, previous: &Self) -> Self {
         TrafficCounter {
-            bytes: previous.bytes + (self.bytes - previous.bytes) * index / steps,
-            packets: previous.packets + (self.packets - previous.packets) * index / steps,
+            bytes: epoch_delta(self.bytes, previous.bytes),
+            packets: epoch_delta(self.packets, previous.packets),
         }
     }
 }
 
+impl Persist for TrafficCounter {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.bytes.write_to(out)?;
+        self.packets.write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(TrafficCounter {
+            bytes: u64::read_from(input)?,
+            packets: u64::read_from(input)?,
+        })
+    }
+}
+
+impl ops::AddAssign<&TrafficCounter> for TrafficCounter {
+    fn add_assign(&mut self, rhs: &TrafficCounter) {
+        self.bytes += rhs.bytes;
+        self.packets += rhs.packets;
+    }
+}
+
+pub const RESOLUTION: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 pub struct Trafic {
-    in_count: RRD<TrafficCounter>,
-    out_count: RRD<TrafficCounter>,
+    in_count: MultiRRD<TrafficCounter>,
+    out_count: MultiRRD<TrafficCounter>,
 }
 
 impl Trafic {
-    pub fn new(retain: Duration) -> Trafic {
-        let now = Utc::now().naive_utc();
+    pub fn new(archives: &[Archive]) -> Trafic {
+        Trafic::with_start(Utc::now().naive_utc(), archives)
+    }
+
+    pub fn with_start(start: NaiveDateTime, archives: &[Archive]) -> Trafic {
         Trafic {
-            in_count: RRD::new(now, Duration::from_secs(1), retain),
-            out_count: RRD::new(now, Duration::from_secs(1), retain),
+            in_count: MultiRRD::new(start, archives),
+            out_count: MultiRRD::new(start, archives),
         }
     }
 
@@ -52,24 +133,363 @@ impl Trafic {
     }
 
     pub fn snapshot_in_rates(&self) -> (NaiveDateTime, Vec<TrafficRate>) {
+        let in_count = self.in_count.finest();
+
         (
-            self.in_count.first_timestamp(),
-            self.in_count
-                .iter()
-                .tuple_windows()
-                .map(|(prev, current)| TrafficRate::from_counter(prev, current))
-                .collect(),
+            in_count.first_timestamp(),
+            rates_between(
+                in_count,
+                in_count.first_timestamp(),
+                in_count.last_timestamp(),
+            ),
         )
     }
 
     pub fn snapshot_out_rates(&self) -> (NaiveDateTime, Vec<TrafficRate>) {
+        let out_count = self.out_count.finest();
+
         (
-            self.in_count.first_timestamp(),
-            self.in_count
-                .iter()
-                .tuple_windows()
-                .map(|(prev, current)| TrafficRate::from_counter(prev, current))
-                .collect(),
+            out_count.first_timestamp(),
+            rates_between(
+                out_count,
+                out_count.first_timestamp(),
+                out_count.last_timestamp(),
+            ),
+        )
+    }
+
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        let in_count = self.in_count.finest();
+        let out_count = self.out_count.finest();
+        let start = in_count.first_timestamp().max(out_count.first_timestamp());
+        let end = in_count.last_timestamp().min(out_count.last_timestamp());
+
+        TrafficSnapshot {
+            start,
+            resolution_secs: in_count.resolution().num_seconds() as u64,
+            in_rates: rates_between(in_count, start, end),
+            out_rates: rates_between(out_count, start, end),
+        }
+    }
+
+    /// Average in and out rates of `from..=to` in buckets of `step`, though never finer than
+    /// the archive used
+    pub fn resample(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        step: chrono::Duration,
+        aggregator: Aggregator,
+    ) -> TrafficSnapshot {
+        let step = step.max(self.in_count.covering(from).resolution());
+
+        TrafficSnapshot {
+            start: from,
+            resolution_secs: step.num_seconds() as u64,
+            in_rates: resample_rates(&self.in_count, from, to, step, aggregator),
+            out_rates: resample_rates(&self.out_count, from, to, step, aggregator),
+        }
+    }
+
+    /// Total in and out traffic since `from`, taken from the finest archive reaching back that far
+    pub fn volume_since(&self, from: NaiveDateTime) -> (TrafficCounter, TrafficCounter) {
+        (
+            volume_since(&self.in_count, from),
+            volume_since(&self.out_count, from),
+        )
+    }
+
+    pub fn last_in(&self) -> TrafficCounter {
+        *self.in_count.finest().last().1
+    }
+
+    pub fn last_out(&self) -> TrafficCounter {
+        *self.out_count.finest().last().1
+    }
+
+    pub fn current_in_rate(&self) -> TrafficRate {
+        current_rate(self.in_count.finest())
+    }
+
+    pub fn current_out_rate(&self) -> TrafficRate {
+        current_rate(self.out_count.finest())
+    }
+}
+
+impl Trafic {
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.in_count.write_to(out)?;
+        self.out_count.write_to(out)
+    }
+
+    pub fn read_from<R: Read>(input: &mut R, archives: &[Archive]) -> io::Result<Self> {
+        Ok(Trafic {
+            in_count: MultiRRD::read_from(input, archives)?,
+            out_count: MultiRRD::read_from(input, archives)?,
+        })
+    }
+}
+
+/// In and out rates covering the same time range, i.e. `in_rates[i]` and `out_rates[i]`
+/// both start at `start + i * resolution_secs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrafficSnapshot {
+    pub start: NaiveDateTime,
+    pub resolution_secs: u64,
+    #[serde(rename = "in")]
+    pub in_rates: Vec<TrafficRate>,
+    #[serde(rename = "out")]
+    pub out_rates: Vec<TrafficRate>,
+}
+
+fn rates_between(
+    count: &RRD<TrafficCounter>,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Vec<TrafficRate> {
+    count
+        .range(start, end)
+        .tuple_windows()
+        .map(|(prev, current)| TrafficRate::from_counter(prev, current))
+        .collect()
+}
+
+/// `DeltaSum` is the average rate of a step, `Max` and `Mean` aggregate the rates of the slots
+/// of the archive within a step
+fn resample_rates(
+    count: &MultiRRD<TrafficCounter>,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    step: chrono::Duration,
+    aggregator: Aggregator,
+) -> Vec<TrafficRate> {
+    let count = count.covering(from);
+
+    match aggregator {
+        Aggregator::DeltaSum => count
+            .resample(from, to, step, aggregator)
+            .iter()
+            .map(|(_, delta)| TrafficRate::from_delta(delta, step))
+            .collect(),
+        _ => slot_deltas(count, from, to)
+            .resample(from, to, step, aggregator)
+            .iter()
+            .map(|(_, delta)| TrafficRate::from_delta(delta, count.resolution()))
+            .collect(),
+    }
+}
+
+/// Traffic of every slot of `from..=to`, i.e. the difference to the slot before
+fn slot_deltas(
+    count: &RRD<TrafficCounter>,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> RRD<TrafficCounter> {
+    let resolution = count.resolution();
+    // One more, `from` is rounded down to the resolution
+    let slots = (to - from).num_milliseconds() / resolution.num_milliseconds() + 2;
+    let resolution = resolution.to_std().unwrap_or(RESOLUTION);
+    let mut deltas = RRD::new(from, resolution, resolution * slots as u32);
+
+    for ((_, previous), (timestamp, current)) in
+        count.range(from - count.resolution(), to).tuple_windows()
+    {
+        if timestamp >= from {
+            deltas.put(timestamp, current.delta(previous));
+        }
+    }
+    deltas
+}
+
+fn volume_since(count: &MultiRRD<TrafficCounter>, from: NaiveDateTime) -> TrafficCounter {
+    let count = count.covering(from);
+
+    count
+        .range(from, count.last_timestamp())
+        .tuple_windows()
+        .fold(TrafficCounter::default(), |mut volume, (prev, current)| {
+            volume += &current.1.delta(prev.1);
+            volume
+        })
+}
+
+fn current_rate(count: &RRD<TrafficCounter>) -> TrafficRate {
+    let len = count.len();
+
+    if len < 2 {
+        return Default::default();
+    }
+    match (count.get(len - 2), count.get(len - 1)) {
+        (Some(prev), Some(current)) => TrafficRate::from_counter(prev, current),
+        _ => Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, NaiveTime};
+    use spectral::prelude::*;
+
+    fn start() -> NaiveDateTime {
+        NaiveDateTime::new(
+            NaiveDate::from_ymd(2000, 1, 1),
+            NaiveTime::from_hms(0, 0, 0),
         )
     }
+
+    fn bytes(rates: &[TrafficRate]) -> Vec<u64> {
+        rates.iter().map(TrafficRate::bytes_per_sec).collect()
+    }
+
+    fn archives() -> Vec<Archive> {
+        vec![
+            Archive {
+                resolution: RESOLUTION,
+                retain: Duration::from_secs(60),
+            },
+            Archive {
+                resolution: Duration::from_secs(60),
+                retain: Duration::from_secs(3600),
+            },
+        ]
+    }
+
+    fn traffic() -> Trafic {
+        let mut traffic = Trafic::with_start(start(), &archives());
+
+        for i in 0..4 {
+            let timestamp = start() + chrono::Duration::seconds(i);
+            traffic.put_in(timestamp, i as u64 * 1000, i as u64 * 10);
+            traffic.put_out(timestamp, i as u64 * 100, i as u64);
+        }
+
+        traffic
+    }
+
+    #[test]
+    fn test_snapshot_directions() {
+        let traffic = traffic();
+        let (in_start, in_rates) = traffic.snapshot_in_rates();
+        let (out_start, out_rates) = traffic.snapshot_out_rates();
+
+        assert_that(&in_start).is_equal_to(start());
+        assert_that(&bytes(&in_rates)).is_equal_to(vec![1000, 1000, 1000]);
+        assert_that(&out_start).is_equal_to(start());
+        assert_that(&bytes(&out_rates)).is_equal_to(vec![100, 100, 100]);
+    }
+
+    #[test]
+    fn test_snapshot_aligned() {
+        let mut traffic = traffic();
+
+        // out lags behind by one tick
+        traffic.put_in(start() + chrono::Duration::seconds(4), 5000, 50);
+
+        let snapshot = traffic.snapshot();
+
+        assert_that(&snapshot.start).is_equal_to(start());
+        assert_that(&snapshot.resolution_secs).is_equal_to(1);
+        assert_that(&bytes(&snapshot.in_rates)).is_equal_to(vec![1000, 1000, 1000]);
+        assert_that(&bytes(&snapshot.out_rates)).is_equal_to(vec![100, 100, 100]);
+    }
+
+    #[test]
+    fn test_volume_since() {
+        let mut traffic = Trafic::with_start(start(), &archives());
+
+        for i in 0..300 {
+            let timestamp = start() + chrono::Duration::seconds(i);
+            traffic.put_in(timestamp, i as u64 * 1000, i as u64);
+            traffic.put_out(timestamp, i as u64 * 10, 0);
+        }
+
+        // Still in the 1s archive
+        let (volume_in, volume_out) =
+            traffic.volume_since(start() + chrono::Duration::seconds(259));
+        assert_that(&volume_in.bytes).is_equal_to(40_000);
+        assert_that(&volume_in.packets).is_equal_to(40);
+        assert_that(&volume_out.bytes).is_equal_to(400);
+
+        // Only the 1m archive reaches back to the start, its first slot just holds the
+        // reading at the end of the first minute
+        let (volume_in, volume_out) = traffic.volume_since(start());
+        assert_that(&volume_in.bytes).is_equal_to(240_000);
+        assert_that(&volume_out.bytes).is_equal_to(2_400);
+    }
+
+    #[test]
+    fn test_resample() {
+        let mut traffic = Trafic::with_start(start(), &archives());
+
+        for i in 0..30 {
+            let timestamp = start() + chrono::Duration::seconds(i);
+            traffic.put_in(timestamp, i as u64 * 1000, i as u64);
+            traffic.put_out(timestamp, i as u64 * 100, 0);
+        }
+
+        let resample = |aggregator| {
+            traffic.resample(
+                start() + chrono::Duration::seconds(10),
+                start() + chrono::Duration::seconds(29),
+                chrono::Duration::seconds(10),
+                aggregator,
+            )
+        };
+        let snapshot = resample(Aggregator::DeltaSum);
+
+        assert_that(&snapshot.start).is_equal_to(start() + chrono::Duration::seconds(10));
+        assert_that(&snapshot.resolution_secs).is_equal_to(10);
+        assert_that(&bytes(&snapshot.in_rates)).is_equal_to(vec![1000, 1000]);
+        assert_that(&bytes(&snapshot.out_rates)).is_equal_to(vec![100, 100]);
+    }
+
+    #[test]
+    fn test_resample_peak() {
+        let mut traffic = Trafic::with_start(start(), &archives());
+        let mut total = 0;
+
+        // A burst of 5000 bytes/s in the 5th second of every 10
+        for i in 0..30 {
+            total += if i % 10 == 5 { 5000 } else { 1000 };
+            traffic.put_in(start() + chrono::Duration::seconds(i), total, 0);
+        }
+        let resample = |aggregator| {
+            let snapshot = traffic.resample(
+                start() + chrono::Duration::seconds(10),
+                start() + chrono::Duration::seconds(29),
+                chrono::Duration::seconds(10),
+                aggregator,
+            );
+            bytes(&snapshot.in_rates)
+        };
+
+        assert_that(&resample(Aggregator::DeltaSum)).is_equal_to(vec![1400, 1400]);
+        assert_that(&resample(Aggregator::Mean)).is_equal_to(vec![1400, 1400]);
+        assert_that(&resample(Aggregator::Max)).is_equal_to(vec![5000, 5000]);
+    }
+
+    #[test]
+    fn test_counter_reset() {
+        let mut traffic = Trafic::with_start(start(), &archives());
+        let at = |secs: i64| start() + chrono::Duration::seconds(secs);
+
+        traffic.put_in(at(0), 0, 0);
+        traffic.put_in(at(1), 1000, 10);
+        traffic.put_in(at(2), 2000, 20);
+        // Counter reset
+        traffic.put_in(at(3), 500, 5);
+        traffic.put_in(at(4), 1500, 15);
+        // Reset within the same slot, the latest reading wins
+        traffic.put_in(at(5), 2500, 25);
+        traffic.put_in(at(5), 200, 2);
+        // Reset somewhere in a gap
+        traffic.put_in(at(10), 100, 1);
+
+        let (_, rates) = traffic.snapshot_in_rates();
+
+        assert_that(&bytes(&rates))
+            .is_equal_to(vec![1000, 1000, 500, 1000, 200, 20, 20, 20, 20, 20]);
+        assert_that(&traffic.last_in().bytes).is_equal_to(100);
+    }
 }
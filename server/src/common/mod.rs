@@ -1,8 +1,16 @@
+pub mod checkpoint;
 mod counter;
+mod networks;
 pub mod parse;
+mod poll;
+mod prefix_trie;
 mod rate;
+pub mod source;
 mod subnet;
 
 pub use counter::*;
+pub use networks::*;
+pub use poll::*;
+pub use prefix_trie::*;
 pub use rate::*;
 pub use subnet::*;
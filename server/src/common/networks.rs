@@ -0,0 +1,109 @@
+use super::{PrefixTrie, Subnet};
+use std::net::IpAddr;
+use std::rc::Rc;
+
+#[derive(Debug, Default)]
+struct Class {
+    local: bool,
+    exclude: bool,
+    group: Option<Rc<str>>,
+}
+
+/// Decides which addresses are local hosts and to which named group they belong
+#[derive(Debug, Default)]
+pub struct LocalNetworks {
+    names: Vec<Rc<str>>,
+    classes: PrefixTrie<Class>,
+}
+
+impl LocalNetworks {
+    pub fn new(local: Vec<Subnet>, exclude: Vec<Subnet>) -> LocalNetworks {
+        let mut networks = LocalNetworks::default();
+        for subnet in local {
+            networks
+                .classes
+                .get_or_insert_with(subnet, Default::default)
+                .local = true;
+        }
+        for subnet in exclude {
+            networks
+                .classes
+                .get_or_insert_with(subnet, Default::default)
+                .exclude = true;
+        }
+        networks
+    }
+
+    /// Subnets of a group are local as well, the most specific group wins if they overlap
+    pub fn add_group(&mut self, name: &str, subnet: Subnet) {
+        let name = match self.names.iter().find(|group| &***group == name) {
+            Some(group) => group.clone(),
+            None => {
+                self.names.push(Rc::from(name));
+                self.names[self.names.len() - 1].clone()
+            }
+        };
+        let class = self.classes.get_or_insert_with(subnet, Default::default);
+        if class.group.is_none() {
+            class.group = Some(name);
+        }
+    }
+
+    /// `None` if `addr` is not a local host, otherwise its group (if any)
+    pub fn lookup(&self, addr: &IpAddr) -> Option<Option<&Rc<str>>> {
+        let mut local = false;
+        let mut group = None;
+
+        for class in self.classes.matches(addr) {
+            if class.exclude {
+                return None;
+            }
+            if class.group.is_some() {
+                group = class.group.as_ref();
+            }
+            local |= class.local || class.group.is_some();
+        }
+        if local {
+            Some(group)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    #[test]
+    fn test_lookup() {
+        let mut networks = LocalNetworks::new(
+            vec!["192.168.0.0/16".parse().unwrap()],
+            vec![
+                "192.168.3.1/32".parse().unwrap(),
+                "fe80::/10".parse().unwrap(),
+            ],
+        );
+        networks.add_group("guests", "192.168.10.0/24".parse().unwrap());
+        networks.add_group("iot", "10.0.0.0/24".parse().unwrap());
+        networks.add_group("guests", "fd00:10::/64".parse().unwrap());
+        networks.add_group("printers", "10.0.0.16/28".parse().unwrap());
+        networks.add_group("other", "10.0.0.16/28".parse().unwrap());
+
+        let lookup = |addr: &str| {
+            networks
+                .lookup(&addr.parse().unwrap())
+                .map(|group| group.map(|group| group.to_string()))
+        };
+
+        assert_that(&lookup("192.168.3.10")).contains_value(None);
+        assert_that(&lookup("192.168.3.1")).is_none();
+        assert_that(&lookup("192.168.10.5")).contains_value(Some("guests".to_string()));
+        assert_that(&lookup("fd00:10::5")).contains_value(Some("guests".to_string()));
+        assert_that(&lookup("10.0.0.7")).contains_value(Some("iot".to_string()));
+        assert_that(&lookup("10.0.0.17")).contains_value(Some("printers".to_string()));
+        assert_that(&lookup("fe80::1")).is_none();
+        assert_that(&lookup("1.2.3.4")).is_none();
+    }
+}
@@ -1,23 +1,40 @@
 use super::Subnet;
 use nom::branch::alt;
+use nom::bytes::complete::take_while1;
 use nom::character::complete::{char, digit1, hex_digit1};
-use nom::combinator::{map, map_res};
+use nom::combinator::{map, map_res, not, opt};
 use nom::error::{ErrorKind, ParseError};
 use nom::multi::many_m_n;
 use nom::sequence::{preceded, terminated};
 use nom::{Err, IResult};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+/// Legacy prefix of full octets, e.g. `192.168.3.` or `192.168.3`
 pub fn ipv4_subnet<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Subnet, E> {
-    let (input, prefix) = many_m_n(
-        1,
+    let (input, mut prefix) = many_m_n(
+        0,
         3,
         map_res(terminated(digit1, char('.')), str::parse::<u8>),
     )(input)?;
+    // A bare last octet, unless it is actually the first segment of an IPv6 prefix like `100::`
+    let (input, last) = opt(terminated(
+        map_res(digit1, str::parse::<u8>),
+        not(take_while1(|ch: char| ch.is_ascii_hexdigit() || ch == ':')),
+    ))(input)?;
+    prefix.extend(last);
+    if prefix.is_empty() || prefix.len() > 3 {
+        return Err(Err::Error(E::from_error_kind(input, ErrorKind::Digit)));
+    }
+    let mut octets = [0u8; 4];
+    octets[..prefix.len()].copy_from_slice(&prefix);
 
-    Ok((input, Subnet::V4(prefix)))
+    Ok((
+        input,
+        Subnet::V4(Ipv4Addr::from(octets), 8 * prefix.len() as u8),
+    ))
 }
 
+/// Legacy prefix of full segments, e.g. `1234::`
 pub fn ipv6_subnet<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Subnet, E> {
     let (input, prefix) = many_m_n(
         1,
@@ -26,12 +43,32 @@ pub fn ipv6_subnet<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a st
             u16::from_str_radix(s, 16)
         }),
     )(input)?;
+    let (input, _) = opt(char(':'))(input)?;
+    let mut segments = [0u16; 8];
+    segments[..prefix.len()].copy_from_slice(&prefix);
+
+    Ok((
+        input,
+        Subnet::V6(Ipv6Addr::from(segments), 16 * prefix.len() as u8),
+    ))
+}
+
+/// CIDR notation, e.g. `10.0.0.0/22` or `2001:db8::/32`
+pub fn cidr_subnet<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Subnet, E> {
+    let (input, addr) = map_res(
+        take_while1(|ch: char| ch.is_ascii_hexdigit() || ch == '.' || ch == ':'),
+        str::parse::<IpAddr>,
+    )(input)?;
+    let (input, prefix_len) = map_res(preceded(char('/'), digit1), str::parse::<u8>)(input)?;
 
-    Ok((input, Subnet::V6(prefix)))
+    match Subnet::new(addr, prefix_len) {
+        Some(subnet) => Ok((input, subnet)),
+        None => Err(Err::Error(E::from_error_kind(input, ErrorKind::TooLarge))),
+    }
 }
 
 pub fn subnet<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Subnet, E> {
-    alt((ipv4_subnet, ipv6_subnet))(input)
+    alt((cidr_subnet, ipv4_subnet, ipv6_subnet))(input)
 }
 
 pub fn ipv4_addr<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Ipv4Addr, E> {
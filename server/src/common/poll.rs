@@ -0,0 +1,147 @@
+use crate::model::CollectorHealth;
+use chrono::{NaiveDateTime, Utc};
+use log::{error, info, warn};
+use std::io;
+use std::time::Duration;
+
+/// Poll schedule of a collector, consecutive failures are retried with exponential backoff
+pub struct Poller {
+    name: &'static str,
+    interval: Duration,
+    max_backoff: Duration,
+    started: NaiveDateTime,
+    failures: u32,
+    errors: u64,
+    entries: usize,
+    last_success: Option<NaiveDateTime>,
+    last_error: Option<String>,
+    warning: Option<String>,
+}
+
+impl Poller {
+    pub fn new(name: &'static str, interval: Duration, max_backoff: Duration) -> Poller {
+        Poller {
+            name,
+            interval,
+            max_backoff,
+            started: Utc::now().naive_utc(),
+            failures: 0,
+            errors: 0,
+            entries: 0,
+            last_success: None,
+            last_error: None,
+            warning: None,
+        }
+    }
+
+    /// Record the result of a poll (the number of processed entries) and get the delay until the next one
+    pub fn record(&mut self, result: io::Result<usize>) -> Duration {
+        match result {
+            Ok(entries) => {
+                if self.failures > 0 {
+                    info!(
+                        "{} recovered after {} failed attempts",
+                        self.name, self.failures
+                    );
+                }
+                self.failures = 0;
+                self.entries = entries;
+                self.last_success = Some(Utc::now().naive_utc());
+                self.interval
+            }
+            Err(error) => {
+                self.failures += 1;
+                self.errors += 1;
+                let delay = self.backoff();
+                self.last_error = Some(error.to_string());
+                error!(
+                    "{} failed: {} (retry in {})",
+                    self.name,
+                    error,
+                    humantime::format_duration(delay)
+                );
+                delay
+            }
+        }
+    }
+
+    /// Set or clear a problem that does not make the poll fail, changes are logged
+    pub fn warn(&mut self, warning: Option<String>) {
+        if warning == self.warning {
+            return;
+        }
+        match &warning {
+            Some(warning) => warn!("{}: {}", self.name, warning),
+            None => info!("{}: problem resolved", self.name),
+        }
+        self.warning = warning;
+    }
+
+    fn backoff(&self) -> Duration {
+        let factor = 1u32 << self.failures.min(16);
+
+        (self.interval * factor)
+            .min(self.max_backoff)
+            .max(self.interval)
+    }
+
+    pub fn health(&self) -> CollectorHealth {
+        CollectorHealth {
+            started: self.started,
+            last_success: self.last_success,
+            entries: self.entries,
+            errors: self.errors,
+            consecutive_failures: self.failures,
+            last_error: self.last_error.clone(),
+            warning: self.warning.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    fn failure() -> io::Result<usize> {
+        Err(io::ErrorKind::NotFound.into())
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut poller = Poller::new("Test", Duration::from_millis(500), Duration::from_secs(5));
+
+        assert_that(&poller.record(Ok(10))).is_equal_to(Duration::from_millis(500));
+        assert_that(&poller.record(failure())).is_equal_to(Duration::from_secs(1));
+        assert_that(&poller.record(failure())).is_equal_to(Duration::from_secs(2));
+        assert_that(&poller.record(failure())).is_equal_to(Duration::from_secs(4));
+        assert_that(&poller.record(failure())).is_equal_to(Duration::from_secs(5));
+        for _ in 0..40 {
+            poller.record(failure());
+        }
+        assert_that(&poller.health().consecutive_failures).is_equal_to(44);
+
+        assert_that(&poller.health().entries).is_equal_to(10);
+        assert_that(&poller.health().last_error).is_some();
+
+        assert_that(&poller.record(Ok(20))).is_equal_to(Duration::from_millis(500));
+
+        let health = poller.health();
+        assert_that(&health.entries).is_equal_to(20);
+        assert_that(&health.errors).is_equal_to(44);
+        assert_that(&health.consecutive_failures).is_equal_to(0);
+        assert_that(&health.last_success).is_some();
+    }
+
+    #[test]
+    fn test_warning() {
+        let mut poller = Poller::new("Test", Duration::from_secs(1), Duration::from_secs(5));
+
+        poller.warn(Some("Something is off".to_string()));
+        poller.record(Ok(1));
+        assert_that(&poller.health().warning).contains_value("Something is off".to_string());
+
+        poller.warn(None);
+        assert_that(&poller.health().warning).is_none();
+    }
+}
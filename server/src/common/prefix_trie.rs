@@ -0,0 +1,216 @@
+use super::Subnet;
+use std::net::IpAddr;
+
+// Index of the root node, which is never a child and thereby doubles as "no child"
+const ROOT: u32 = 0;
+
+#[derive(Debug)]
+struct Node<V> {
+    // Address bits aligned to the most significant bit, only the first `prefix_len` are set
+    prefix: u128,
+    prefix_len: u8,
+    children: [u32; 2],
+    value: Option<V>,
+}
+
+impl<V> Node<V> {
+    fn new(prefix: u128, prefix_len: u8) -> Node<V> {
+        Node {
+            prefix: prefix & mask(prefix_len),
+            prefix_len,
+            children: [ROOT; 2],
+            value: None,
+        }
+    }
+
+    fn matches(&self, bits: u128) -> bool {
+        (bits ^ self.prefix) & mask(self.prefix_len) == 0
+    }
+}
+
+/// Binary trie over the address bits. Chains without branches are collapsed into a single node,
+/// so a lookup only visits the nodes where the stored subnets diverge.
+#[derive(Debug)]
+pub struct PrefixTrie<V> {
+    v4: Vec<Node<V>>,
+    v6: Vec<Node<V>>,
+}
+
+impl<V> Default for PrefixTrie<V> {
+    fn default() -> Self {
+        PrefixTrie {
+            v4: vec![Node::new(0, 0)],
+            v6: vec![Node::new(0, 0)],
+        }
+    }
+}
+
+fn mask(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+fn bits(addr: &IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u128::from(u32::from(*addr)) << 96,
+        IpAddr::V6(addr) => u128::from(*addr),
+    }
+}
+
+fn bit(bits: u128, index: u8) -> usize {
+    ((bits >> (127 - index)) & 1) as usize
+}
+
+impl<V> PrefixTrie<V> {
+    /// Value stored for `subnet`, created by `default` if there is none yet
+    pub fn get_or_insert_with<F>(&mut self, subnet: Subnet, default: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let (nodes, bits, prefix_len) = match subnet {
+            Subnet::V4(network, prefix_len) => (&mut self.v4, bits(&network.into()), prefix_len),
+            Subnet::V6(network, prefix_len) => (&mut self.v6, bits(&network.into()), prefix_len),
+        };
+        // Invariant: the prefix of `current` is a prefix of `bits`
+        let mut current = ROOT as usize;
+
+        while nodes[current].prefix_len < prefix_len {
+            let branch = bit(bits, nodes[current].prefix_len);
+            let child = nodes[current].children[branch] as usize;
+
+            if child == ROOT as usize {
+                nodes.push(Node::new(bits, prefix_len));
+                nodes[current].children[branch] = (nodes.len() - 1) as u32;
+                current = nodes.len() - 1;
+                break;
+            }
+            let common = ((bits ^ nodes[child].prefix).leading_zeros() as u8)
+                .min(prefix_len)
+                .min(nodes[child].prefix_len);
+
+            if common < nodes[child].prefix_len {
+                // Split the collapsed chain where it diverges from `subnet`
+                let mut split = Node::new(bits, common);
+                split.children[bit(nodes[child].prefix, common)] = child as u32;
+                nodes.push(split);
+                nodes[current].children[branch] = (nodes.len() - 1) as u32;
+                current = nodes.len() - 1;
+            } else {
+                current = child;
+            }
+        }
+        nodes[current].value.get_or_insert_with(default)
+    }
+
+    /// Values of all subnets containing `addr`, from the least to the most specific
+    pub fn matches<'a>(&'a self, addr: &IpAddr) -> impl Iterator<Item = &'a V> + 'a {
+        let (nodes, max_len) = match addr {
+            IpAddr::V4(_) => (&self.v4, 32),
+            IpAddr::V6(_) => (&self.v6, 128),
+        };
+        let bits = bits(addr);
+
+        std::iter::successors(Some(&nodes[ROOT as usize]), move |node| {
+            if node.prefix_len >= max_len {
+                return None;
+            }
+            match node.children[bit(bits, node.prefix_len)] {
+                ROOT => None,
+                child => Some(&nodes[child as usize]).filter(|child| child.matches(bits)),
+            }
+        })
+        .filter_map(|node| node.value.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    #[test]
+    fn test_matches() {
+        let mut trie = PrefixTrie::default();
+        let mut insert = |subnet: &str, value| {
+            trie.get_or_insert_with(subnet.parse().unwrap(), || value);
+        };
+
+        insert("10.0.0.0/8", "wide");
+        insert("10.1.0.0/16", "narrow");
+        insert("10.1.0.0/16", "duplicate");
+        insert("10.1.2.3/32", "host");
+        insert("2001:db8::/32", "v6");
+        insert("0.0.0.0/0", "everything");
+        insert("10.1.2.0/24", "split");
+        insert("10.1.3.0/24", "sibling");
+
+        let matches = |addr: &str| {
+            trie.matches(&addr.parse().unwrap())
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert_that(&matches("10.2.0.1")).is_equal_to(vec!["everything", "wide"]);
+        assert_that(&matches("10.1.200.1")).is_equal_to(vec!["everything", "wide", "narrow"]);
+        assert_that(&matches("10.1.2.3")).is_equal_to(vec![
+            "everything",
+            "wide",
+            "narrow",
+            "split",
+            "host",
+        ]);
+        assert_that(&matches("10.1.3.1")).is_equal_to(vec![
+            "everything",
+            "wide",
+            "narrow",
+            "sibling",
+        ]);
+        assert_that(&matches("10.1.2.4")).is_equal_to(vec![
+            "everything",
+            "wide",
+            "narrow",
+            "split",
+        ]);
+        assert_that(&matches("11.0.0.1")).is_equal_to(vec!["everything"]);
+        assert_that(&matches("2001:db8:1::1")).is_equal_to(vec!["v6"]);
+        assert_that(&matches("2001:db9::1")).is_empty();
+        // IPv4 and IPv6 are kept apart, even with identical leading bits
+        assert_that(&matches("a00::")).is_empty();
+    }
+
+    #[test]
+    fn test_matches_like_contains() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        // Few distinct leading bits, so the subnets overlap a lot
+        let mut addr = || IpAddr::from(((random() as u32) & 0xf0f0_f0f0).to_be_bytes());
+        let subnets = (0..200)
+            .map(|i| Subnet::new(addr(), (i * 7 % 33) as u8).unwrap())
+            .collect::<Vec<_>>();
+        let mut trie = PrefixTrie::default();
+
+        for subnet in &subnets {
+            trie.get_or_insert_with(*subnet, || *subnet);
+        }
+        for _ in 0..1000 {
+            let addr = addr();
+            let mut expected = subnets
+                .iter()
+                .filter(|subnet| subnet.contains(&addr))
+                .cloned()
+                .collect::<Vec<_>>();
+            expected.sort_by_key(|subnet| match subnet {
+                Subnet::V4(_, prefix_len) | Subnet::V6(_, prefix_len) => *prefix_len,
+            });
+            expected.dedup();
+
+            assert_that(&trie.matches(&addr).cloned().collect::<Vec<_>>()).is_equal_to(expected);
+        }
+    }
+}
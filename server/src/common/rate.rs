@@ -1,37 +1,157 @@
 use super::TrafficCounter;
+use crate::minirrd::Aggregatable;
 use chrono::NaiveDateTime;
+use serde_derive::Serialize;
+use std::ops;
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Serialize)]
 pub struct TrafficRate {
     bytes_per_sec: u64,
     packets_per_sec: u64,
 }
 
 impl TrafficRate {
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    pub fn from_delta(delta: &TrafficCounter, duration: chrono::Duration) -> TrafficRate {
+        let secs = duration.num_seconds();
+
+        if secs < 1 {
+            return Default::default();
+        }
+
+        TrafficRate {
+            bytes_per_sec: delta.bytes / secs as u64,
+            packets_per_sec: delta.packets / secs as u64,
+        }
+    }
+
     pub fn from_counter(
         prev: (NaiveDateTime, &TrafficCounter),
         current: (NaiveDateTime, &TrafficCounter),
     ) -> TrafficRate {
-        let secs = (current.0 - prev.0).num_seconds();
+        TrafficRate::from_delta(&current.1.delta(prev.1), current.0 - prev.0)
+    }
+}
 
-        if secs < 1 {
-            return Default::default();
+impl ops::AddAssign<&TrafficRate> for TrafficRate {
+    fn add_assign(&mut self, rhs: &TrafficRate) {
+        self.bytes_per_sec += rhs.bytes_per_sec;
+        self.packets_per_sec += rhs.packets_per_sec;
+    }
+}
+
+pub fn sum_rates<I>(
+    resolution: chrono::Duration,
+    series: I,
+) -> Option<(NaiveDateTime, Vec<TrafficRate>)>
+where
+    I: IntoIterator<Item = (NaiveDateTime, Vec<TrafficRate>)>,
+{
+    let series = series.into_iter().collect::<Vec<_>>();
+    let start = series.iter().map(|(start, _)| *start).min()?;
+    let end = series
+        .iter()
+        .map(|(start, rates)| *start + resolution * rates.len() as i32)
+        .max()?;
+    let resolution_millis = resolution.num_milliseconds();
+    let mut sum = vec![
+        TrafficRate::default();
+        ((end - start).num_milliseconds() / resolution_millis) as usize
+    ];
+
+    for (series_start, rates) in series {
+        let offset = ((series_start - start).num_milliseconds() / resolution_millis) as usize;
+        for (i, rate) in rates.iter().enumerate() {
+            sum[offset + i] += rate;
         }
+    }
 
-        let bytes_per_sec = if prev.1.bytes < current.1.bytes {
-            (current.1.bytes - prev.1.bytes) / secs as u64
-        } else {
-            current.1.bytes / secs as u64
-        };
-        let packets_per_sec = if prev.1.packets < current.1.packets {
-            (current.1.packets - prev.1.packets) / secs as u64
-        } else {
-            current.1.packets / secs as u64
-        };
+    Some((start, sum))
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, NaiveTime};
+    use spectral::prelude::*;
+
+    fn rate(bytes_per_sec: u64) -> TrafficRate {
         TrafficRate {
             bytes_per_sec,
-            packets_per_sec,
+            packets_per_sec: bytes_per_sec / 100,
         }
     }
+
+    #[test]
+    fn test_sum_rates_aligned_by_timestamp() {
+        let start = NaiveDateTime::new(
+            NaiveDate::from_ymd(2000, 1, 1),
+            NaiveTime::from_hms(0, 0, 0),
+        );
+        let (sum_start, sum) = sum_rates(
+            chrono::Duration::seconds(1),
+            vec![
+                (start, vec![rate(100), rate(200), rate(300)]),
+                (
+                    start + chrono::Duration::seconds(2),
+                    vec![rate(1000), rate(2000)],
+                ),
+            ],
+        )
+        .unwrap();
+
+        assert_that(&sum_start).is_equal_to(start);
+        assert_that(
+            &sum.iter()
+                .map(TrafficRate::bytes_per_sec)
+                .collect::<Vec<u64>>(),
+        )
+        .is_equal_to(vec![100, 200, 1300, 2000]);
+        assert_that(
+            &sum.iter()
+                .map(|rate| rate.packets_per_sec)
+                .collect::<Vec<u64>>(),
+        )
+        .is_equal_to(vec![1, 2, 13, 20]);
+    }
+
+    #[test]
+    fn test_from_counter() {
+        let start = NaiveDateTime::new(
+            NaiveDate::from_ymd(2000, 1, 1),
+            NaiveTime::from_hms(0, 0, 0),
+        );
+        let rate = |prev: u64, current: u64| {
+            TrafficRate::from_counter(
+                (
+                    start,
+                    &TrafficCounter {
+                        bytes: prev,
+                        packets: prev,
+                    },
+                ),
+                (
+                    start + chrono::Duration::seconds(2),
+                    &TrafficCounter {
+                        bytes: current,
+                        packets: current,
+                    },
+                ),
+            )
+            .bytes_per_sec()
+        };
+
+        assert_that(&rate(1000, 3000)).is_equal_to(1000);
+        assert_that(&rate(3000, 3000)).is_equal_to(0);
+        // Counter was reset in between
+        assert_that(&rate(3000, 400)).is_equal_to(200);
+    }
+
+    #[test]
+    fn test_sum_rates_empty() {
+        assert_that(&sum_rates(chrono::Duration::seconds(1), vec![])).is_none();
+    }
 }
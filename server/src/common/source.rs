@@ -0,0 +1,262 @@
+use actix::{
+    fut, Actor, ActorFuture, Addr, AsyncContext, Context, Handler, Message, SyncArbiter,
+    SyncContext,
+};
+use futures::Future;
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+#[cfg(test)]
+use std::sync::Mutex;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running command is checked for completion
+const COMMAND_POLL: Duration = Duration::from_millis(10);
+
+/// Input of a collector, read anew on every poll
+pub trait Source: Send + Sync {
+    fn read(&self) -> io::Result<Box<dyn Read>>;
+
+    fn describe(&self) -> String;
+}
+
+pub struct FileSource(pub PathBuf);
+
+impl Source for FileSource {
+    fn read(&self) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(&self.0)?))
+    }
+
+    fn describe(&self) -> String {
+        self.0.display().to_string()
+    }
+}
+
+/// Scripted inputs, every read returns the next one. The last input is repeated forever.
+#[cfg(test)]
+pub struct MemorySource(Mutex<VecDeque<Vec<u8>>>);
+
+#[cfg(test)]
+impl MemorySource {
+    pub fn new<I, T>(inputs: I) -> MemorySource
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Vec<u8>>,
+    {
+        MemorySource(Mutex::new(inputs.into_iter().map(Into::into).collect()))
+    }
+}
+
+#[cfg(test)]
+impl Source for MemorySource {
+    fn read(&self) -> io::Result<Box<dyn Read>> {
+        let mut inputs = self.0.lock().unwrap();
+        let input = if inputs.len() > 1 {
+            inputs.pop_front()
+        } else {
+            inputs.front().cloned()
+        };
+
+        match input {
+            Some(input) => Ok(Box::new(Cursor::new(input))),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "No input")),
+        }
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+}
+
+/// Output of a command, e.g. `ssh router cat /proc/net/nf_conntrack`. The command is killed if it
+/// does not finish within `timeout`, since the next poll waits for it.
+pub struct CommandSource {
+    pub command: Vec<String>,
+    pub timeout: Duration,
+}
+
+impl Source for CommandSource {
+    fn read(&self) -> io::Result<Box<dyn Read>> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Drained in the background, otherwise a full pipe would stall the command
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+        let deadline = Instant::now() + self.timeout;
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                // The drain threads end as soon as the pipes are closed
+                child.kill()?;
+                child.wait()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} timed out after {:?}", program, self.timeout),
+                ));
+            }
+            thread::sleep(COMMAND_POLL);
+        };
+        let stdout = join(stdout)?;
+
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed ({}): {}",
+                program,
+                status,
+                String::from_utf8_lossy(&join(stderr)?).trim()
+            )));
+        }
+        Ok(Box::new(Cursor::new(stdout)))
+    }
+
+    fn describe(&self) -> String {
+        self.command.join(" ")
+    }
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut content = vec![];
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut content)?;
+        }
+        Ok(content)
+    })
+}
+
+fn join(handle: thread::JoinHandle<io::Result<Vec<u8>>>) -> io::Result<Vec<u8>> {
+    handle
+        .join()
+        .map_err(|_| io::Error::other("Reading command output panicked"))?
+}
+
+/// Reads a source on a thread of its own. All collectors share the arbiter of the system, so
+/// a slow command or netlink dump would stall all of them (and the HTTP handlers) otherwise.
+pub struct Reader(Arc<dyn Source>);
+
+impl Reader {
+    pub fn start(source: Arc<dyn Source>) -> Addr<Reader> {
+        SyncArbiter::start(1, move || Reader(source.clone()))
+    }
+}
+
+impl Actor for Reader {
+    type Context = SyncContext<Self>;
+}
+
+/// The whole input of the source
+pub struct ReadAll;
+
+impl Message for ReadAll {
+    type Result = io::Result<Vec<u8>>;
+}
+
+impl Handler<ReadAll> for Reader {
+    type Result = io::Result<Vec<u8>>;
+
+    fn handle(&mut self, _: ReadAll, _: &mut SyncContext<Reader>) -> Self::Result {
+        let mut input = vec![];
+
+        self.0.read()?.read_to_end(&mut input)?;
+        Ok(input)
+    }
+}
+
+/// Read the source of `reader` in the background and hand the input to `process` of the actor
+pub fn read_later<A, F>(reader: &Addr<Reader>, ctx: &mut Context<A>, process: F)
+where
+    A: Actor<Context = Context<A>>,
+    F: FnOnce(&mut A, io::Result<Vec<u8>>, &mut Context<A>) + 'static,
+{
+    let read = reader.send(ReadAll).then(|result| {
+        result.unwrap_or_else(|error| Err(io::Error::other(error.to_string())))
+    });
+
+    ctx.spawn(fut::wrap_future(read).then(move |input, actor, ctx| {
+        process(actor, input, ctx);
+        fut::ok(())
+    }));
+}
+
+/// Command if configured, the file otherwise
+pub fn from_config(
+    file: &str,
+    command: &Option<Vec<String>>,
+    timeout: Duration,
+) -> Box<dyn Source> {
+    match command {
+        Some(command) => Box::new(CommandSource {
+            command: command.clone(),
+            timeout,
+        }),
+        None => Box::new(FileSource(PathBuf::from(file))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    fn read_to_string(source: &dyn Source) -> io::Result<String> {
+        let mut content = String::new();
+        source.read()?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    #[test]
+    fn test_memory_source() {
+        let source = MemorySource::new(vec!["first", "second"]);
+
+        assert_that(&read_to_string(&source).unwrap()).is_equal_to("first".to_string());
+        assert_that(&read_to_string(&source).unwrap()).is_equal_to("second".to_string());
+        assert_that(&read_to_string(&source).unwrap()).is_equal_to("second".to_string());
+        assert_that(&read_to_string(&MemorySource::new(Vec::<String>::new()))).is_err();
+    }
+
+    fn command(command: &[&str], timeout: Duration) -> CommandSource {
+        CommandSource {
+            command: command.iter().map(ToString::to_string).collect(),
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_command_source() {
+        let source = command(&["echo", "hello"], Duration::from_secs(10));
+
+        assert_that(&read_to_string(&source).unwrap()).is_equal_to("hello\n".to_string());
+        assert_that(&read_to_string(&command(
+            &["false"],
+            Duration::from_secs(10),
+        )))
+        .is_err();
+    }
+
+    #[test]
+    fn test_command_source_timeout() {
+        let source = command(&["sleep", "10"], Duration::from_millis(100));
+        let start = Instant::now();
+
+        let error = read_to_string(&source).unwrap_err();
+
+        assert_that(&error.kind()).is_equal_to(io::ErrorKind::TimedOut);
+        assert_that(&start.elapsed()).is_less_than(Duration::from_secs(5));
+    }
+}
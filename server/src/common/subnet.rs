@@ -1,52 +1,81 @@
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Subnet {
-    V4(Vec<u8>),
-    V6(Vec<u16>),
+    V4(Ipv4Addr, u8),
+    V6(Ipv6Addr, u8),
+}
+
+fn v4_mask(prefix_len: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+fn v6_mask(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
 }
 
 impl Subnet {
+    /// Subnet of `addr` with the host bits cleared, `None` if `prefix_len` is too long
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Subnet> {
+        match addr {
+            IpAddr::V4(addr) if prefix_len <= 32 => Some(Subnet::V4(
+                Ipv4Addr::from(u32::from(addr) & v4_mask(prefix_len)),
+                prefix_len,
+            )),
+            IpAddr::V6(addr) if prefix_len <= 128 => Some(Subnet::V6(
+                Ipv6Addr::from(u128::from(addr) & v6_mask(prefix_len)),
+                prefix_len,
+            )),
+            _ => None,
+        }
+    }
+
     pub fn contains(&self, addr: &IpAddr) -> bool {
         match (self, addr) {
-            (Subnet::V4(prefix), IpAddr::V4(v4_addr)) => {
-                let octets = v4_addr.octets();
-                for (i, p) in prefix.iter().enumerate() {
-                    if octets[i] != *p {
-                        return false;
-                    }
-                }
-                true
+            (Subnet::V4(network, prefix_len), IpAddr::V4(v4_addr)) => {
+                u32::from(*v4_addr) & v4_mask(*prefix_len) == u32::from(*network)
             }
-            (Subnet::V6(prefix), IpAddr::V6(v6_addr)) => {
-                let segments = v6_addr.segments();
-                for (i, p) in prefix.iter().enumerate() {
-                    if segments[i] != *p {
-                        return false;
-                    }
-                }
-                true
+            (Subnet::V6(network, prefix_len), IpAddr::V6(v6_addr)) => {
+                u128::from(*v6_addr) & v6_mask(*prefix_len) == u128::from(*network)
             }
             _ => false,
         }
     }
 }
 
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Subnet::V4(network, prefix_len) => write!(f, "{}/{}", network, prefix_len),
+            Subnet::V6(network, prefix_len) => write!(f, "{}/{}", network, prefix_len),
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match super::parse::subnet::<()>(s) {
+            Ok(("", subnet)) => Ok(subnet),
+            _ => Err(()),
+        }
+    }
+}
+
 impl Serialize for Subnet {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let s = match self {
-            Subnet::V4(prefix) => prefix.iter().map(|p| format!("{}.", p)).collect::<String>(),
-            Subnet::V6(prefix) => prefix
-                .iter()
-                .map(|p| format!("{:x}.", p))
-                .collect::<String>(),
-        };
-        serializer.serialize_str(&s)
+        serializer.collect_str(self)
     }
 }
 
@@ -65,17 +94,76 @@ impl<'de> de::Visitor<'de> for SubnetVisitor {
     type Value = Subnet;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "IP4 or IP6 subnet prefix")
+        write!(formatter, "IP4 or IP6 subnet in CIDR notation or as prefix")
     }
 
     fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        if let Ok((_, subnet)) = super::parse::subnet::<()>(s) {
-            Ok(subnet)
-        } else {
-            Err(de::Error::invalid_value(de::Unexpected::Str(s), &self))
+        s.parse()
+            .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(s), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+
+    fn subnet(s: &str) -> Subnet {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_contains() {
+        let lan = subnet("10.0.0.0/22");
+
+        assert_that(&lan.contains(&"10.0.3.255".parse().unwrap())).is_true();
+        assert_that(&lan.contains(&"10.0.4.0".parse().unwrap())).is_false();
+        assert_that(&lan.contains(&"::1".parse().unwrap())).is_false();
+
+        let lan = subnet("2001:db8:abcd:12::/62");
+
+        assert_that(&lan.contains(&"2001:db8:abcd:13::1".parse().unwrap())).is_true();
+        assert_that(&lan.contains(&"2001:db8:abcd:14::1".parse().unwrap())).is_false();
+
+        assert_that(&subnet("0.0.0.0/0").contains(&"1.2.3.4".parse().unwrap())).is_true();
+        assert_that(&subnet("1.2.3.4/32").contains(&"1.2.3.4".parse().unwrap())).is_true();
+    }
+
+    #[test]
+    fn test_legacy_prefix() {
+        assert_that(&subnet("192.168.3.")).is_equal_to(subnet("192.168.3.0/24"));
+        assert_that(&subnet("192.168.3")).is_equal_to(subnet("192.168.3.0/24"));
+        assert_that(&subnet("10.")).is_equal_to(subnet("10.0.0.0/8"));
+        assert_that(&subnet("1234::")).is_equal_to(subnet("1234::/16"));
+        assert_that(&subnet("1234:5678:")).is_equal_to(subnet("1234:5678::/32"));
+        // Decimal looking first segments of IPv6 prefixes
+        assert_that(&subnet("100::")).is_equal_to(subnet("100::/16"));
+        assert_that(&subnet("64:ff9b:")).is_equal_to(subnet("64:ff9b::/32"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_that(&"10.0.0.0/33".parse::<Subnet>()).is_err();
+        assert_that(&"::/129".parse::<Subnet>()).is_err();
+        assert_that(&"10.0.0.0/".parse::<Subnet>()).is_err();
+        assert_that(&"eth0".parse::<Subnet>()).is_err();
+        assert_that(&"192.168.3.4".parse::<Subnet>()).is_err();
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        for s in &["10.0.0.0/22", "2001:db8:abcd:12::/62", "1234::"] {
+            let subnet = subnet(s);
+            let serialized = serde_json::to_string(&subnet).unwrap();
+
+            assert_that(&serde_json::from_str::<Subnet>(&serialized).unwrap()).is_equal_to(subnet);
         }
+        assert_that(&serde_json::to_string(&subnet("10.0.1.2/22")).unwrap())
+            .is_equal_to("\"10.0.0.0/22\"".to_string());
+        assert_that(&serde_json::to_string(&subnet("1234::")).unwrap())
+            .is_equal_to("\"1234::/16\"".to_string());
     }
 }
@@ -1,23 +1,381 @@
-use crate::common::Subnet;
+use crate::common::{self, LocalNetworks, Subnet};
+use crate::interfaces::InterfaceSubnets;
+use crate::minirrd::Archive;
 use log::error;
+use serde::{de, Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
+use std::fmt;
 use std::fs::File;
 use std::io::{ErrorKind, Read, Result};
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub local_subnets: Vec<Subnet>,
+    pub local_subnets: Vec<LocalSubnet>,
+    #[serde(default)]
+    pub exclude_subnets: Vec<Subnet>,
+    #[serde(default)]
+    pub subnets: Vec<SubnetGroup>,
     pub wan_interface: String,
     #[serde(default = "default_conntrack_file")]
     pub conntrack_file: String,
+    #[serde(default)]
+    pub conntrack_source: ConntrackSource,
+    pub conntrack_command: Option<Vec<String>>,
+    #[serde(default)]
+    pub conntrack_events: bool,
+    #[serde(default = "default_conntrack_acct_file")]
+    pub conntrack_acct_file: String,
     #[serde(default = "default_device_file")]
     pub device_file: String,
+    pub device_command: Option<Vec<String>>,
     #[serde(default = "default_lease_file")]
     pub leases_file: String,
-    #[serde(default = "default_retain_data", with = "humantime_serde")]
+    pub leases_command: Option<Vec<String>>,
+    #[serde(default = "default_interfaces_inet6_file")]
+    pub interfaces_inet6_file: String,
+    #[serde(default = "default_interfaces_route_file")]
+    pub interfaces_route_file: String,
+    #[serde(
+        default = "default_retain_data",
+        deserialize_with = "deserialize_retain_data",
+        serialize_with = "humantime_serde::serialize"
+    )]
     pub retain_data: Duration,
+    #[serde(default, deserialize_with = "deserialize_archives")]
+    pub archives: Vec<Archive>,
+    #[serde(default = "default_max_remotes")]
+    pub max_remotes: usize,
+    pub state_dir: Option<PathBuf>,
+    #[serde(default = "default_checkpoint_interval", with = "humantime_serde")]
+    pub checkpoint_interval: Duration,
+    #[serde(default)]
+    pub poll: PollConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+}
+
+/// Entry of `local_subnets`, either a fixed subnet or all subnets of an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalSubnet {
+    Subnet(Subnet),
+    Interface(String),
+}
+
+impl fmt::Display for LocalSubnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LocalSubnet::Subnet(subnet) => write!(f, "{}", subnet),
+            LocalSubnet::Interface(interface) => write!(f, "iface:{}", interface),
+        }
+    }
+}
+
+impl serde::Serialize for LocalSubnet {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for LocalSubnet {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_string(LocalSubnetVisitor)
+    }
+}
+
+struct LocalSubnetVisitor;
+
+impl<'de> de::Visitor<'de> for LocalSubnetVisitor {
+    type Value = LocalSubnet;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "subnet in CIDR notation or iface:<interface>")
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        match s.strip_prefix("iface:") {
+            Some(interface) if !interface.is_empty() => {
+                Ok(LocalSubnet::Interface(interface.to_string()))
+            }
+            Some(_) => Err(de::Error::invalid_value(de::Unexpected::Str(s), &self)),
+            None => s
+                .parse()
+                .map(LocalSubnet::Subnet)
+                .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(s), &self)),
+        }
+    }
+}
+
+/// Named subnet, local hosts are aggregated per name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubnetGroup {
+    pub name: String,
+    pub cidr: Subnet,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConntrackSource {
+    /// Parse `conntrack_file`
+    #[default]
+    Proc,
+    /// Dump the table via NETLINK_NETFILTER
+    Netlink,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    #[serde(default = "default_bind", deserialize_with = "deserialize_bind")]
+    pub bind: Vec<BindAddr>,
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            bind: default_bind(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindAddr::Tcp(addr) => write!(f, "{}", addr),
+            BindAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl serde::Serialize for BindAddr {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for BindAddr {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_string(BindAddrVisitor)
+    }
+}
+
+/// Without any listen address the server would silently serve nothing
+fn deserialize_bind<'de, D>(deserializer: D) -> std::result::Result<Vec<BindAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bind = <Vec<BindAddr> as serde::Deserialize>::deserialize(deserializer)?;
+
+    if bind.is_empty() {
+        return Err(de::Error::invalid_length(0, &"at least one listen address"));
+    }
+    Ok(bind)
+}
+
+/// Checkpoints store the number of archives, including the one of `retain_data`, as a byte
+const MAX_ARCHIVES: usize = 255;
+
+/// The finest archive has to hold at least one value
+fn deserialize_retain_data<'de, D>(deserializer: D) -> std::result::Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let retain_data: Duration = humantime_serde::deserialize(deserializer)?;
+
+    if retain_data < common::RESOLUTION {
+        return Err(de::Error::custom(format!(
+            "retain_data has to be at least {:?}",
+            common::RESOLUTION
+        )));
+    }
+    Ok(retain_data)
+}
+
+/// Every archive has to consolidate whole values of the collectors and hold at least one slot
+fn deserialize_archives<'de, D>(deserializer: D) -> std::result::Result<Vec<Archive>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let archives = <Vec<Archive> as serde::Deserialize>::deserialize(deserializer)?;
+
+    if archives.len() >= MAX_ARCHIVES {
+        return Err(de::Error::invalid_length(
+            archives.len(),
+            &"at most 254 archives",
+        ));
+    }
+    for archive in &archives {
+        let resolution = archive.resolution.as_millis();
+        let step = common::RESOLUTION.as_millis();
+
+        if resolution < step || resolution % step != 0 {
+            return Err(de::Error::custom(format!(
+                "archive resolution {:?} is not a multiple of {:?}",
+                archive.resolution,
+                common::RESOLUTION
+            )));
+        }
+        if archive.retain < archive.resolution {
+            return Err(de::Error::custom(format!(
+                "archive retain {:?} is shorter than its resolution {:?}",
+                archive.retain, archive.resolution
+            )));
+        }
+    }
+    Ok(archives)
+}
+
+struct BindAddrVisitor;
+
+impl<'de> de::Visitor<'de> for BindAddrVisitor {
+    type Value = BindAddr;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "socket address or unix:<path>")
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(BindAddr::Unix(PathBuf::from(path)))
+        } else if let Ok(addr) = s.parse::<SocketAddr>() {
+            Ok(BindAddr::Tcp(addr))
+        } else {
+            Err(de::Error::invalid_value(de::Unexpected::Str(s), &self))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub certificate: String,
+    pub private_key: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    #[default]
+    Read,
+    Admin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenConfig {
+    pub token: String,
+    #[serde(default)]
+    pub scope: Scope,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserConfig {
+    pub name: String,
+    pub password_hash: String,
+    #[serde(default)]
+    pub scope: Scope,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || !self.users.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollConfig {
+    #[serde(default = "default_poll_interval", with = "humantime_nonzero")]
+    pub conntrack: Duration,
+    #[serde(default = "default_poll_interval", with = "humantime_nonzero")]
+    pub device: Duration,
+    #[serde(default = "default_poll_interval", with = "humantime_nonzero")]
+    pub leases: Duration,
+    #[serde(default = "default_interfaces_interval", with = "humantime_nonzero")]
+    pub interfaces: Duration,
+    #[serde(default = "default_max_backoff", with = "humantime_nonzero")]
+    pub max_backoff: Duration,
+    #[serde(default = "default_command_timeout", with = "humantime_nonzero")]
+    pub command_timeout: Duration,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            conntrack: default_poll_interval(),
+            device: default_poll_interval(),
+            leases: default_poll_interval(),
+            interfaces: default_interfaces_interval(),
+            max_backoff: default_max_backoff(),
+            command_timeout: default_command_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    #[serde(default = "default_max_remote_series")]
+    pub max_remote_series: usize,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            max_remote_series: default_max_remote_series(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthConfig {
+    #[serde(default = "default_stale_after", with = "humantime_chrono")]
+    pub stale_after: chrono::Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            stale_after: default_stale_after(),
+        }
+    }
 }
 
 fn default_device_file() -> String {
@@ -28,15 +386,154 @@ fn default_conntrack_file() -> String {
     "/proc/net/nf_conntrack".to_string()
 }
 
+fn default_conntrack_acct_file() -> String {
+    "/proc/sys/net/netfilter/nf_conntrack_acct".to_string()
+}
+
 fn default_lease_file() -> String {
     "/var/lib/misc/dnsmasq.leases".to_string()
 }
 
+fn default_interfaces_inet6_file() -> String {
+    "/proc/net/if_inet6".to_string()
+}
+
+fn default_interfaces_route_file() -> String {
+    "/proc/net/route".to_string()
+}
+
 fn default_retain_data() -> Duration {
     Duration::from_secs(300)
 }
 
+fn default_max_remotes() -> usize {
+    1000
+}
+
+fn default_checkpoint_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_poll_interval() -> Duration {
+    Duration::from_millis(500)
+}
+
+fn default_interfaces_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_backoff() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_command_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_stale_after() -> chrono::Duration {
+    chrono::Duration::seconds(30)
+}
+
+/// Like `humantime_serde`, but rejects 0, e.g. a poll interval of 0 would keep the collectors busy
+/// all the time
+mod humantime_nonzero {
+    use serde::{de, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        humantime_serde::serialize(duration, serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let duration: Duration = humantime_serde::deserialize(deserializer)?;
+
+        if duration == Duration::from_secs(0) {
+            return Err(de::Error::invalid_value(
+                de::Unexpected::Str("0s"),
+                &"a duration greater than 0",
+            ));
+        }
+        Ok(duration)
+    }
+}
+
+/// Like `humantime_serde`, but for `chrono::Duration`, so durations it can not represent are
+/// rejected when the config is loaded
+mod humantime_chrono {
+    use serde::{de, ser, Deserializer, Serializer};
+
+    pub fn serialize<S>(duration: &chrono::Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        humantime_serde::serialize(&duration.to_std().map_err(ser::Error::custom)?, serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<chrono::Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let duration = humantime_serde::deserialize(deserializer)?;
+
+        chrono::Duration::from_std(duration)
+            .map_err(|_| de::Error::custom(format!("duration {:?} is too long", duration)))
+    }
+}
+
+fn default_bind() -> Vec<BindAddr> {
+    vec![BindAddr::Tcp(SocketAddr::from(([0, 0, 0, 0], 8080)))]
+}
+
+fn default_max_remote_series() -> usize {
+    100
+}
+
 impl Config {
+    /// `retain_data` at the collector resolution followed by any coarser archives
+    pub fn archives(&self) -> Vec<Archive> {
+        let mut archives = vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: self.retain_data,
+        }];
+        archives.extend(self.archives.iter().cloned());
+        archives
+    }
+
+    /// Interfaces whose subnets have to be discovered
+    pub fn local_interfaces(&self) -> Vec<&str> {
+        self.local_subnets
+            .iter()
+            .filter_map(|local| match local {
+                LocalSubnet::Interface(interface) => Some(interface.as_str()),
+                LocalSubnet::Subnet(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn local_networks(&self, interfaces: &InterfaceSubnets) -> LocalNetworks {
+        let local = self
+            .local_subnets
+            .iter()
+            .flat_map(|local| match local {
+                LocalSubnet::Subnet(subnet) => vec![*subnet],
+                LocalSubnet::Interface(interface) => {
+                    interfaces.get(interface).cloned().unwrap_or_default()
+                }
+            })
+            .collect();
+        let mut networks = LocalNetworks::new(local, self.exclude_subnets.clone());
+        for group in &self.subnets {
+            networks.add_group(&group.name, group.cidr);
+        }
+        networks
+    }
+
     pub fn read<P: AsRef<Path>>(path: P) -> Result<Config> {
         let mut file = File::open(path)?;
         let mut config_toml = String::new();
@@ -56,7 +553,9 @@ impl Config {
 mod tests {
     use super::*;
     use crate::common::Subnet;
+    use crate::minirrd::Archive;
     use spectral::prelude::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
     use std::time::Duration;
 
     #[test]
@@ -69,37 +568,291 @@ mod tests {
         let config = toml::from_str::<Config>(simple).unwrap();
 
         assert_that(&config.local_subnets).is_equal_to(vec![
-            Subnet::V4(vec![192, 168, 3]),
-            Subnet::V6(vec![0x1234]),
+            LocalSubnet::Subnet("192.168.3.0/24".parse().unwrap()),
+            LocalSubnet::Subnet("1234::/16".parse().unwrap()),
         ]);
+        assert_that(&config.local_interfaces()).is_empty();
+        assert_that(&config.exclude_subnets).is_empty();
+        assert_that(&config.subnets).is_empty();
         assert_that(&config.wan_interface).is_equal_to("eth0".to_string());
         assert_that(&config.conntrack_file).is_equal_to("/proc/net/nf_conntrack".to_string());
+        assert_that(&config.conntrack_acct_file)
+            .is_equal_to("/proc/sys/net/netfilter/nf_conntrack_acct".to_string());
+        assert_that(&config.conntrack_source).is_equal_to(ConntrackSource::Proc);
+        assert_that(&config.conntrack_events).is_false();
+        assert_that(&config.conntrack_command).is_none();
+        assert_that(&config.device_command).is_none();
+        assert_that(&config.leases_command).is_none();
         assert_that(&config.device_file).is_equal_to("/proc/net/dev".to_string());
         assert_that(&config.leases_file).is_equal_to("/var/lib/misc/dnsmasq.leases".to_string());
+        assert_that(&config.interfaces_inet6_file).is_equal_to("/proc/net/if_inet6".to_string());
+        assert_that(&config.interfaces_route_file).is_equal_to("/proc/net/route".to_string());
         assert_that(&config.retain_data).is_equal_to(Duration::from_secs(300));
+        assert_that(&config.archives()).is_equal_to(vec![Archive {
+            resolution: Duration::from_secs(1),
+            retain: Duration::from_secs(300),
+        }]);
+        assert_that(&config.http.bind)
+            .is_equal_to(vec![BindAddr::Tcp("0.0.0.0:8080".parse().unwrap())]);
+        assert_that(&config.state_dir).is_none();
+        assert_that(&config.checkpoint_interval).is_equal_to(Duration::from_secs(300));
+        assert_that(&config.poll.conntrack).is_equal_to(Duration::from_millis(500));
+        assert_that(&config.poll.device).is_equal_to(Duration::from_millis(500));
+        assert_that(&config.poll.leases).is_equal_to(Duration::from_millis(500));
+        assert_that(&config.poll.interfaces).is_equal_to(Duration::from_secs(30));
+        assert_that(&config.poll.max_backoff).is_equal_to(Duration::from_secs(60));
+        assert_that(&config.poll.command_timeout).is_equal_to(Duration::from_secs(10));
+        assert_that(&config.http.tls.is_none()).is_true();
+        assert_that(&config.auth.is_enabled()).is_false();
+        assert_that(&config.metrics.max_remote_series).is_equal_to(100);
+        assert_that(&config.health.stale_after).is_equal_to(chrono::Duration::seconds(30));
     }
 
     #[test]
     fn test_decode_config_full() {
         let full = r#"
-            local_subnets = ["192.168.3.", "1234::"]
+            local_subnets = ["10.0.0.0/22", "2001:db8:abcd:12::/62", "iface:br-lan"]
+            exclude_subnets = ["10.0.0.1/32", "fe80::/10"]
             wan_interface = "eth0"
             conntrack_file = "/da/conntrack"
+            conntrack_acct_file = "/da/conntrack_acct"
+            conntrack_source = "netlink"
+            conntrack_events = true
             device_file = "/da/device"
             leases_file = "/da/leases"
+            leases_command = ["ssh", "router", "cat", "/var/lib/misc/dnsmasq.leases"]
+            interfaces_inet6_file = "/da/if_inet6"
+            interfaces_route_file = "/da/route"
             retain_data = "10m"
+            archives = [
+                { resolution = "1m", retain = "24h" },
+                { resolution = "1h", retain = "90days" },
+            ]
+            state_dir = "/da/state"
+            checkpoint_interval = "1m"
+
+            [[subnets]]
+            name = "guests"
+            cidr = "10.0.10.0/24"
+
+            [poll]
+            conntrack = "1s"
+            leases = "10s"
+            interfaces = "1m"
+            max_backoff = "5m"
+
+            [http]
+            bind = ["192.168.3.1:8081", "[::1]:8081", "unix:/run/nftracker.sock"]
+
+            [http.tls]
+            certificate = "/da/cert.pem"
+            private_key = "/da/key.pem"
+
+            [[auth.tokens]]
+            token = "secret"
+
+            [[auth.users]]
+            name = "admin"
+            password_hash = "$2b$04$hash"
+            scope = "admin"
+
+            [metrics]
+            max_remote_series = 20
+
+            [health]
+            stale_after = "2m"
         "#;
 
         let config = toml::from_str::<Config>(full).unwrap();
 
         assert_that(&config.local_subnets).is_equal_to(vec![
-            Subnet::V4(vec![192, 168, 3]),
-            Subnet::V6(vec![0x1234]),
+            LocalSubnet::Subnet(Subnet::V4(Ipv4Addr::new(10, 0, 0, 0), 22)),
+            // Host bits are cleared
+            LocalSubnet::Subnet(Subnet::V6(
+                Ipv6Addr::new(0x2001, 0xdb8, 0xabcd, 0x10, 0, 0, 0, 0),
+                62,
+            )),
+            LocalSubnet::Interface("br-lan".to_string()),
         ]);
+        assert_that(&config.local_interfaces()).is_equal_to(vec!["br-lan"]);
+        assert_that(&config.exclude_subnets).is_equal_to(vec![
+            Subnet::V4(Ipv4Addr::new(10, 0, 0, 1), 32),
+            "fe80::/10".parse().unwrap(),
+        ]);
+        assert_that(&config.subnets).is_equal_to(vec![SubnetGroup {
+            name: "guests".to_string(),
+            cidr: "10.0.10.0/24".parse().unwrap(),
+        }]);
         assert_that(&config.wan_interface).is_equal_to("eth0".to_string());
         assert_that(&config.conntrack_file).is_equal_to("/da/conntrack".to_string());
+        assert_that(&config.conntrack_acct_file).is_equal_to("/da/conntrack_acct".to_string());
+        assert_that(&config.conntrack_source).is_equal_to(ConntrackSource::Netlink);
+        assert_that(&config.conntrack_events).is_true();
         assert_that(&config.device_file).is_equal_to("/da/device".to_string());
         assert_that(&config.leases_file).is_equal_to("/da/leases".to_string());
+        assert_that(&config.interfaces_inet6_file).is_equal_to("/da/if_inet6".to_string());
+        assert_that(&config.interfaces_route_file).is_equal_to("/da/route".to_string());
+        assert_that(&config.leases_command).contains_value(vec![
+            "ssh".to_string(),
+            "router".to_string(),
+            "cat".to_string(),
+            "/var/lib/misc/dnsmasq.leases".to_string(),
+        ]);
         assert_that(&config.retain_data).is_equal_to(Duration::from_secs(600));
+        assert_that(&config.archives()).is_equal_to(vec![
+            Archive {
+                resolution: Duration::from_secs(1),
+                retain: Duration::from_secs(600),
+            },
+            Archive {
+                resolution: Duration::from_secs(60),
+                retain: Duration::from_secs(86_400),
+            },
+            Archive {
+                resolution: Duration::from_secs(3_600),
+                retain: Duration::from_secs(90 * 86_400),
+            },
+        ]);
+        assert_that(&config.http.bind).is_equal_to(vec![
+            BindAddr::Tcp("192.168.3.1:8081".parse().unwrap()),
+            BindAddr::Tcp("[::1]:8081".parse().unwrap()),
+            BindAddr::Unix(PathBuf::from("/run/nftracker.sock")),
+        ]);
+        assert_that(&config.state_dir).contains_value(PathBuf::from("/da/state"));
+        assert_that(&config.checkpoint_interval).is_equal_to(Duration::from_secs(60));
+        assert_that(&config.poll.conntrack).is_equal_to(Duration::from_secs(1));
+        assert_that(&config.poll.device).is_equal_to(Duration::from_millis(500));
+        assert_that(&config.poll.leases).is_equal_to(Duration::from_secs(10));
+        assert_that(&config.poll.interfaces).is_equal_to(Duration::from_secs(60));
+        assert_that(&config.poll.max_backoff).is_equal_to(Duration::from_secs(300));
+        let tls = config.http.tls.unwrap();
+        assert_that(&tls.certificate).is_equal_to("/da/cert.pem".to_string());
+        assert_that(&tls.private_key).is_equal_to("/da/key.pem".to_string());
+        assert_that(&config.auth.is_enabled()).is_true();
+        assert_that(&config.auth.tokens[0].token).is_equal_to("secret".to_string());
+        assert_that(&config.auth.tokens[0].scope).is_equal_to(Scope::Read);
+        assert_that(&config.auth.users[0].name).is_equal_to("admin".to_string());
+        assert_that(&config.auth.users[0].scope).is_equal_to(Scope::Admin);
+        assert_that(&config.metrics.max_remote_series).is_equal_to(20);
+        assert_that(&config.health.stale_after).is_equal_to(chrono::Duration::minutes(2));
+    }
+
+    #[test]
+    fn test_local_networks() {
+        let config = toml::from_str::<Config>(
+            r#"
+            local_subnets = ["192.168.3.0/24", "iface:br-lan", "iface:missing"]
+            wan_interface = "eth0"
+        "#,
+        )
+        .unwrap();
+        let mut interfaces = InterfaceSubnets::new();
+        interfaces.insert(
+            "br-lan".to_string(),
+            vec!["2001:db8:abcd:10::/64".parse().unwrap()],
+        );
+        let networks = config.local_networks(&interfaces);
+
+        assert_that(&networks.lookup(&"192.168.3.10".parse().unwrap())).is_some();
+        assert_that(&networks.lookup(&"2001:db8:abcd:10::5".parse().unwrap())).is_some();
+        assert_that(&networks.lookup(&"2001:db8:abcd:20::5".parse().unwrap())).is_none();
+        assert_that(
+            &toml::from_str::<Config>(
+                r#"
+            local_subnets = ["iface:"]
+            wan_interface = "eth0"
+        "#,
+            )
+            .is_err(),
+        )
+        .is_true();
+    }
+
+    #[test]
+    fn test_bind_addr_round_trip() {
+        let http = HttpConfig {
+            bind: vec![
+                BindAddr::Tcp("[::1]:8081".parse().unwrap()),
+                BindAddr::Unix(PathBuf::from("/run/nftracker.sock")),
+            ],
+            tls: None,
+        };
+        let encoded = toml::to_string(&http).unwrap();
+
+        assert_that(&encoded.contains(r#""unix:/run/nftracker.sock""#)).is_true();
+        assert_that(&toml::from_str::<HttpConfig>(&encoded).unwrap().bind).is_equal_to(http.bind);
+    }
+
+    #[test]
+    fn test_bind_addr_invalid() {
+        assert_that(&toml::from_str::<HttpConfig>(r#"bind = ["localhost"]"#).is_err()).is_true();
+        assert_that(&toml::from_str::<HttpConfig>(r#"bind = []"#).is_err()).is_true();
+    }
+
+    #[test]
+    fn test_stale_after_out_of_range() {
+        assert_that(&toml::from_str::<HealthConfig>(r#"stale_after = "300000000years""#).is_err())
+            .is_true();
+    }
+
+    #[test]
+    fn test_invalid_archives() {
+        let config = |archives: &str| {
+            toml::from_str::<Config>(&format!(
+                r#"
+                local_subnets = ["192.168.3.0/24"]
+                wan_interface = "eth0"
+                {}
+            "#,
+                archives
+            ))
+        };
+
+        assert_that(&config(
+            r#"archives = [{ resolution = "1m", retain = "1h" }]"#,
+        ))
+        .is_ok();
+        assert_that(&config(r#"retain_data = "500ms""#)).is_err();
+        assert_that(&config(
+            r#"archives = [{ resolution = "0s", retain = "1h" }]"#,
+        ))
+        .is_err();
+        assert_that(&config(
+            r#"archives = [{ resolution = "500ms", retain = "1h" }]"#,
+        ))
+        .is_err();
+        assert_that(&config(
+            r#"archives = [{ resolution = "1500ms", retain = "1h" }]"#,
+        ))
+        .is_err();
+        assert_that(&config(
+            r#"archives = [{ resolution = "1h", retain = "1m" }]"#,
+        ))
+        .is_err();
+
+        let too_many = vec![r#"{ resolution = "1m", retain = "1h" }"#; MAX_ARCHIVES];
+        assert_that(&config(&format!("archives = [{}]", too_many.join(", ")))).is_err();
+        assert_that(&config(&format!(
+            "archives = [{}]",
+            too_many[1..].join(", ")
+        )))
+        .is_ok();
+    }
+
+    #[test]
+    fn test_zero_poll_interval() {
+        for field in &[
+            "conntrack",
+            "device",
+            "leases",
+            "interfaces",
+            "max_backoff",
+            "command_timeout",
+        ] {
+            let poll = toml::from_str::<PollConfig>(&format!(r#"{} = "0s""#, field));
+
+            assert_that(&poll.is_err()).is_true();
+        }
+        assert_that(&toml::from_str::<PollConfig>(r#"leases = "1s""#).is_ok()).is_true();
     }
 }
@@ -1,92 +1,477 @@
-use super::parse;
-use super::{Local, Table};
-use crate::common::Subnet;
-use crate::config::Config;
-use actix::{Actor, AsyncContext, Context, Handler, Message};
+use super::{netlink, parse};
+use super::{FlowKey, Local, Remote, Table};
+use crate::common::source::{self, Reader, Source};
+use crate::common::{self, checkpoint, LocalNetworks, Poller, TrafficCounter, TrafficRate};
+use crate::config::{Config, ConntrackSource};
+use crate::interfaces::Discovery;
+use crate::minirrd::Aggregatable;
+use crate::model::{
+    CollectorHealth, GroupTraffic, HostCounters, HostRate, HostSummary, HostTraffic, HostVolume,
+    RatesUpdate, RemoteCounters, RemoteRate, RemoteTraffic, Subscribe, Window,
+};
+use actix::prelude::SendError;
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, MessageResult};
 use chrono::{NaiveDateTime, Utc};
-use log::{debug, error};
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{self, Read};
-use std::time::Duration;
+use itertools::Itertools;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+const CHECKPOINT_FILE: &str = "conntrack.rrd";
+const MISSING_ACCOUNTING: &str =
+    "Conntrack entries have no byte counters, nf_conntrack_acct seems to be disabled";
 
 pub struct ConntrackCollector {
     config: Config,
+    source: Arc<dyn Source>,
+    /// Started with the actor
+    reader: Option<Addr<Reader>>,
+    networks: LocalNetworks,
+    discovery: Option<Discovery>,
     table: Table,
+    events: Option<netlink::Events>,
+    subscribers: Vec<Subscribe>,
+    last_checkpoint: Instant,
+    poller: Poller,
+    acct_disabled: Option<String>,
 }
 
 #[derive(Message)]
 struct Ping;
 
+#[derive(Message)]
+#[rtype(result = "CollectorHealth")]
+pub struct Health;
+
+#[derive(Message)]
+#[rtype(result = "Vec<HostSummary>")]
+pub struct Hosts;
+
+#[derive(Message)]
+#[rtype(result = "Vec<GroupTraffic>")]
+pub struct Groups;
+
+#[derive(Message)]
+#[rtype(result = "Option<Vec<RemoteTraffic>>")]
+pub struct Remotes(pub Local, pub Option<Window>);
+
+#[derive(Message)]
+#[rtype(result = "Option<HostVolume>")]
+pub struct Volume(pub Local, pub NaiveDateTime);
+
+#[derive(Message)]
+#[rtype(result = "Vec<HostTraffic>")]
+pub struct Snapshot;
+
+#[derive(Message)]
+#[rtype(result = "Vec<HostCounters>")]
+pub struct Counters;
+
 struct TableCollector<'a> {
     now: NaiveDateTime,
     table: &'a mut Table,
-    local_subnets: &'a [Subnet],
-    locals: HashSet<Local>,
+    networks: &'a LocalNetworks,
+    locals: HashMap<Local, Option<Rc<str>>>,
+    flows: HashMap<FlowKey, TrafficCounter>,
+    in_deltas: HashMap<(Local, Remote), TrafficCounter>,
+    out_deltas: HashMap<(Local, Remote), TrafficCounter>,
+    stats: PollStats,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct PollStats {
+    flows: usize,
+    entries: usize,
+    accounted: usize,
+}
+
+impl PollStats {
+    /// Without nf_conntrack_acct none of the entries has any counters
+    fn missing_accounting(&self) -> Option<bool> {
+        if self.entries > 0 {
+            Some(self.accounted == 0)
+        } else {
+            None
+        }
+    }
 }
 
 impl<'a> TableCollector<'a> {
-    fn process<I: Read>(table: &mut Table, local_subnets: &[Subnet], input: I) -> io::Result<()> {
+    fn process<F>(
+        table: &'a mut Table,
+        networks: &'a LocalNetworks,
+        parse: F,
+    ) -> io::Result<PollStats>
+    where
+        F: FnOnce(TableCollector<'a>) -> io::Result<TableCollector<'a>>,
+    {
         let collector = TableCollector {
             now: Utc::now().naive_utc(),
             table,
-            local_subnets,
+            networks,
             locals: Default::default(),
+            flows: Default::default(),
+            in_deltas: Default::default(),
+            out_deltas: Default::default(),
+            stats: Default::default(),
         };
-        let collector = parse::parse(input, collector, TableCollector::collect)?;
+        let mut collector = parse(collector)?;
 
-        collector.cleanup();
+        collector.flush();
 
-        Ok(())
+        Ok(collector.stats)
     }
 
     fn collect(mut self, entry: &parse::ConntrackEntry) -> Self {
-        for subnet in self.local_subnets {
-            if subnet.contains(&entry.src) {
-                self.table
-                    .push_out(self.now, entry.src, entry.dst, entry.bytes, entry.packets);
-                self.locals.insert(entry.src);
-                break;
-            }
-            if subnet.contains(&entry.dst) {
-                self.table
-                    .push_in(self.now, entry.dst, entry.src, entry.bytes, entry.packets);
-                self.locals.insert(entry.dst);
-                break;
-            }
+        self.stats.entries += 1;
+        if entry.accounting {
+            self.stats.accounted += 1;
+        }
+        if let Some((local, remote, outgoing)) = self.locate(entry) {
+            self.stats.flows += 1;
+            self.add_flow(entry, local, remote, outgoing, false);
+        }
+        self
+    }
+
+    fn collect_event(mut self, event: netlink::Event, entry: &parse::ConntrackEntry) -> Self {
+        if event != netlink::Event::Destroy {
+            return self;
+        }
+        if let Some((local, remote, outgoing)) = self.locate(entry) {
+            self.add_flow(entry, local, remote, outgoing, true);
         }
         self
     }
 
-    fn cleanup(self) {
-        let obsolete = self
-            .table
-            .connections
-            .keys()
-            .filter(|source| !self.locals.contains(*source))
-            .cloned()
-            .collect::<Vec<Local>>();
+    /// Local and remote end of a flow and whether the local end initiated it, the group of the
+    /// local end is remembered.
+    /// Only the original tuple and the source of the reply tuple are reliable, the destination of
+    /// the reply tuple is the WAN address for masqueraded flows.
+    fn locate(&mut self, entry: &parse::ConntrackEntry) -> Option<(Local, Remote, bool)> {
+        let (local, remote, outgoing, group) =
+            if let Some(group) = self.networks.lookup(&entry.original.src) {
+                (entry.original.src, entry.original.dst, true, group)
+            } else if let Some(group) = self.networks.lookup(&entry.reply.src) {
+                // Inbound, either directly or port forwarded via DNAT
+                (entry.reply.src, entry.original.src, false, group)
+            } else {
+                return None;
+            };
+        self.locals.insert(local, group.cloned());
+
+        Some((local, remote, outgoing))
+    }
+
+    /// Credit the traffic of both directions of a flow. A closed flow is credited with its final
+    /// counters, short-lived flows might never have been part of a poll at all.
+    fn add_flow(
+        &mut self,
+        entry: &parse::ConntrackEntry,
+        local: Local,
+        remote: Remote,
+        outgoing: bool,
+        closed: bool,
+    ) {
+        let (sent, received) = if outgoing {
+            (&entry.original, &entry.reply)
+        } else {
+            (&entry.reply, &entry.original)
+        };
+        let out_delta = self.tuple_delta(entry, sent, closed);
+        let in_delta = self.tuple_delta(entry, received, closed);
+
+        self.table.seen.insert((local, remote), self.now);
+        *self.out_deltas.entry((local, remote)).or_default() += &out_delta;
+        *self.in_deltas.entry((local, remote)).or_default() += &in_delta;
+    }
+
+    fn tuple_delta(
+        &mut self,
+        entry: &parse::ConntrackEntry,
+        tuple: &parse::ConntrackTuple,
+        closed: bool,
+    ) -> TrafficCounter {
+        let flow = FlowKey::new(entry, tuple);
+        let counter = TrafficCounter {
+            bytes: tuple.bytes,
+            packets: tuple.packets,
+        };
+        let previous = if closed {
+            // Events are received after the poll, so the current poll might already contain the flow
+            self.flows
+                .remove(&flow)
+                .or_else(|| self.table.flows.get(&flow).copied())
+        } else {
+            self.flows.insert(flow, counter);
+            self.table.flows.get(&flow).copied()
+        };
+
+        // Without restored flows the first poll is just a baseline, otherwise the whole
+        // history of long lived flows would be credited to a single second
+        if self.table.first_poll {
+            return TrafficCounter::default();
+        }
+        // A flow not seen before is new, all its traffic has to be added
+        match previous {
+            Some(previous) => counter.delta(&previous),
+            None => counter,
+        }
+    }
 
-        for source in obsolete {
-            self.table.connections.remove(&source);
+    /// Idle hosts are kept with their totals, only the groups of the active ones might change.
+    /// The groups are updated first, so the deltas go to the current group totals.
+    fn flush(&mut self) {
+        for (local, group) in self.locals.drain() {
+            match group {
+                Some(group) => self.table.groups.insert(local, group),
+                None => self.table.groups.remove(&local),
+            };
+        }
+        self.table.tick(self.now);
+        for ((local, remote), delta) in self.out_deltas.drain() {
+            self.table.add_out(self.now, local, remote, &delta);
         }
+        for ((local, remote), delta) in self.in_deltas.drain() {
+            self.table.add_in(self.now, local, remote, &delta);
+        }
+        self.table.flows = mem::take(&mut self.flows);
+        self.table.first_poll = false;
     }
 }
 
 impl ConntrackCollector {
     pub fn new(config: Config) -> ConntrackCollector {
-        ConntrackCollector {
-            table: Table::new(config.retain_data),
+        let source: Box<dyn Source> = match (config.conntrack_source, &config.conntrack_command) {
+            (ConntrackSource::Netlink, None) => Box::new(netlink::NetlinkSource),
+            (_, command) => {
+                source::from_config(&config.conntrack_file, command, config.poll.command_timeout)
+            }
+        };
+
+        Self::with_source(config, source)
+    }
+
+    pub fn with_source(config: Config, source: Box<dyn Source>) -> ConntrackCollector {
+        let discovery = if config.local_interfaces().is_empty() {
+            None
+        } else {
+            Some(Discovery::new(&config))
+        };
+
+        Self::with_discovery(config, source, discovery)
+    }
+
+    pub fn with_discovery(
+        config: Config,
+        source: Box<dyn Source>,
+        discovery: Option<Discovery>,
+    ) -> ConntrackCollector {
+        let mut poller = Poller::new(
+            "Process conntrack",
+            config.poll.conntrack,
+            config.poll.max_backoff,
+        );
+        let acct_disabled = Self::check_accounting(&config);
+        poller.warn(acct_disabled.clone());
+
+        let mut collector = ConntrackCollector {
+            table: Self::restore(&config).unwrap_or_else(|| Table::new(config.archives())),
+            events: Self::subscribe_events(&config),
+            poller,
+            acct_disabled,
+            networks: config.local_networks(&Default::default()),
+            discovery,
             config,
+            source: source.into(),
+            reader: None,
+            subscribers: vec![],
+            last_checkpoint: Instant::now(),
+        };
+        collector.refresh_networks();
+        collector
+    }
+
+    /// Follow address changes of the interfaces in `local_subnets`
+    fn refresh_networks(&mut self) {
+        let discovery = match &mut self.discovery {
+            Some(discovery) => discovery,
+            None => return,
+        };
+        match discovery.refresh() {
+            Ok(true) => {
+                for interface in self.config.local_interfaces() {
+                    match discovery.subnets().get(interface) {
+                        Some(subnets) => info!(
+                            "Local subnets of {}: {}",
+                            interface,
+                            subnets.iter().map(ToString::to_string).join(", ")
+                        ),
+                        None => warn!("Interface {} has no addresses", interface),
+                    }
+                }
+                self.networks = self.config.local_networks(discovery.subnets());
+            }
+            Ok(false) => (),
+            Err(error) => error!("Discover interface addresses failed: {}", error),
+        }
+    }
+
+    /// Counters are only available if connection accounting is enabled
+    fn check_accounting(config: &Config) -> Option<String> {
+        match fs::read_to_string(&config.conntrack_acct_file) {
+            Ok(ref acct) if acct.trim() == "0" => Some(format!(
+                "nf_conntrack_acct is disabled ({}), no traffic can be tracked",
+                config.conntrack_acct_file
+            )),
+            Ok(_) => None,
+            Err(error) => {
+                debug!("Unable to check {}: {}", config.conntrack_acct_file, error);
+                None
+            }
+        }
+    }
+
+    fn subscribe_events(config: &Config) -> Option<netlink::Events> {
+        if !config.conntrack_events {
+            return None;
+        }
+        match netlink::Events::subscribe() {
+            Ok(events) => Some(events),
+            Err(error) => {
+                warn!("Unable to subscribe to conntrack events: {}", error);
+                None
+            }
+        }
+    }
+
+    fn restore(config: &Config) -> Option<Table> {
+        let path = config.state_dir.as_ref()?.join(CHECKPOINT_FILE);
+        let archives = config.archives();
+
+        match checkpoint::load(&path, |input| Table::read_from(input, archives)) {
+            Ok(table) => {
+                if let Some(table) = &table {
+                    info!(
+                        "Restored {} local hosts from {}",
+                        table.connections.len(),
+                        path.display()
+                    );
+                }
+                table
+            }
+            Err(error) => {
+                warn!("Ignoring checkpoint {}: {}", path.display(), error);
+                None
+            }
+        }
+    }
+
+    fn checkpoint(&mut self) {
+        if let Some(state_dir) = &self.config.state_dir {
+            let path = state_dir.join(CHECKPOINT_FILE);
+            let table = &self.table;
+
+            debug!("Checkpoint: {}", path.display());
+            if let Err(error) = checkpoint::save(&path, |out| table.write_to(out)) {
+                error!("Checkpoint {} failed: {}", path.display(), error)
+            }
         }
+        self.last_checkpoint = Instant::now();
+    }
+
+    fn process_conntrack(&mut self, input: &[u8]) -> io::Result<usize> {
+        let events = self.events.as_ref();
+
+        let stats = match self.config.conntrack_source {
+            ConntrackSource::Proc => {
+                TableCollector::process(&mut self.table, &self.networks, |collector| {
+                    let collector = parse::parse(input, collector, TableCollector::collect)?;
+                    Self::process_events(events, collector)
+                })
+            }
+            ConntrackSource::Netlink => {
+                TableCollector::process(&mut self.table, &self.networks, |collector| {
+                    let collector = netlink::parse(input, collector, TableCollector::collect)?;
+                    Self::process_events(events, collector)
+                })
+            }
+        }?;
+        self.table
+            .expire(Utc::now().naive_utc(), self.config.max_remotes);
+
+        // Flows created before the sysctl was enabled stay without counters, but any entry that has
+        // counters proves that accounting works now, even if it was disabled at startup
+        match stats.missing_accounting() {
+            Some(true) => {
+                let warning = self
+                    .acct_disabled
+                    .clone()
+                    .unwrap_or_else(|| MISSING_ACCOUNTING.to_string());
+                self.poller.warn(Some(warning))
+            }
+            Some(false) => {
+                self.acct_disabled = None;
+                self.poller.warn(None)
+            }
+            None => (),
+        }
+        Ok(stats.flows)
+    }
+
+    fn process_events<'a>(
+        events: Option<&netlink::Events>,
+        collector: TableCollector<'a>,
+    ) -> io::Result<TableCollector<'a>> {
+        let events = match events.map(netlink::Events::receive) {
+            Some(Ok(events)) => events,
+            Some(Err(error)) => {
+                error!("Receive conntrack events failed: {}", error);
+                return Ok(collector);
+            }
+            None => return Ok(collector),
+        };
+        netlink::parse_events(&events, collector, TableCollector::collect_event)
     }
 
-    fn process_conntrack(&mut self) -> io::Result<()> {
-        debug!("Collecting: {}", self.config.conntrack_file);
-        let file = File::open(&self.config.conntrack_file)?;
-        TableCollector::process(&mut self.table, &self.config.local_subnets, file)?;
+    fn publish(&mut self) {
+        if !self.subscribers.iter().any(Subscribe::is_active) {
+            return;
+        }
+        let update = RatesUpdate::Hosts {
+            timestamp: Utc::now().naive_utc(),
+            hosts: self
+                .table
+                .connections
+                .iter()
+                .map(|(local, remotes)| {
+                    let mut in_rate = TrafficRate::default();
+                    let mut out_rate = TrafficRate::default();
+
+                    for traffic in remotes.values() {
+                        in_rate += &traffic.current_in_rate();
+                        out_rate += &traffic.current_out_rate();
+                    }
+                    HostRate {
+                        addr: *local,
+                        in_rate,
+                        out_rate,
+                    }
+                })
+                .collect(),
+        };
 
-        Ok(())
+        self.subscribers.retain(|subscriber| {
+            !matches!(
+                subscriber.recipient.do_send(update.clone()),
+                Err(SendError::Closed(_))
+            )
+        });
     }
 }
 
@@ -94,10 +479,210 @@ impl Handler<Ping> for ConntrackCollector {
     type Result = ();
 
     fn handle(&mut self, _: Ping, ctx: &mut Context<ConntrackCollector>) {
-        if let Err(error) = self.process_conntrack() {
-            error!("Process conntrack failed: {}", error)
+        let reader = match &self.reader {
+            Some(reader) => reader,
+            None => return,
+        };
+        debug!("Collecting: {}", self.source.describe());
+        source::read_later(reader, ctx, |collector: &mut ConntrackCollector, input, ctx| {
+            let result = input.and_then(|input| collector.process_conntrack(&input));
+            let delay = collector.poller.record(result);
+            collector.publish();
+            if collector.last_checkpoint.elapsed() >= collector.config.checkpoint_interval {
+                collector.checkpoint();
+            }
+            ctx.notify_later(Ping, delay);
+        });
+    }
+}
+
+impl Handler<Health> for ConntrackCollector {
+    type Result = MessageResult<Health>;
+
+    fn handle(&mut self, _: Health, _: &mut Context<ConntrackCollector>) -> Self::Result {
+        MessageResult(self.poller.health())
+    }
+}
+
+impl Handler<Hosts> for ConntrackCollector {
+    type Result = MessageResult<Hosts>;
+
+    fn handle(&mut self, _: Hosts, _: &mut Context<ConntrackCollector>) -> Self::Result {
+        MessageResult(
+            self.table
+                .connections
+                .iter()
+                .map(|(local, remotes)| HostSummary {
+                    addr: *local,
+                    group: self.table.groups.get(local).map(ToString::to_string),
+                    remotes: remotes.len(),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Handler<Groups> for ConntrackCollector {
+    type Result = MessageResult<Groups>;
+
+    fn handle(&mut self, _: Groups, _: &mut Context<ConntrackCollector>) -> Self::Result {
+        let mut groups: Vec<GroupTraffic> = vec![];
+
+        for group in &self.config.subnets {
+            if groups.iter().all(|existing| existing.name != group.name) {
+                groups.push(GroupTraffic {
+                    name: group.name.clone(),
+                    hosts: 0,
+                    in_rate: Default::default(),
+                    out_rate: Default::default(),
+                    in_total: Default::default(),
+                    out_total: Default::default(),
+                });
+            }
         }
-        ctx.notify_later(Ping, Duration::from_millis(500));
+        for (local, remotes) in &self.table.connections {
+            let group = match self.table.groups.get(local) {
+                Some(name) => groups.iter_mut().find(|group| *group.name == **name),
+                None => None,
+            };
+            if let Some(group) = group {
+                group.hosts += 1;
+                for traffic in remotes.values() {
+                    group.in_rate += &traffic.current_in_rate();
+                    group.out_rate += &traffic.current_out_rate();
+                }
+            }
+        }
+        for group in &mut groups {
+            if let Some(totals) = self.table.group_totals.get(group.name.as_str()) {
+                group.in_total = totals.in_count;
+                group.out_total = totals.out_count;
+            }
+        }
+        MessageResult(groups)
+    }
+}
+
+impl Handler<Remotes> for ConntrackCollector {
+    type Result = MessageResult<Remotes>;
+
+    fn handle(&mut self, msg: Remotes, _: &mut Context<ConntrackCollector>) -> Self::Result {
+        let Remotes(local, window) = msg;
+
+        MessageResult(self.table.connections.get(&local).map(|remotes| {
+            remotes
+                .iter()
+                .map(|(remote, traffic)| {
+                    let snapshot = match window {
+                        Some(window) => {
+                            traffic.resample(window.from, window.to, window.step, window.aggregator)
+                        }
+                        None => traffic.snapshot(),
+                    };
+                    RemoteTraffic::new(*remote, snapshot)
+                })
+                .collect()
+        }))
+    }
+}
+
+impl Handler<Volume> for ConntrackCollector {
+    type Result = MessageResult<Volume>;
+
+    fn handle(&mut self, msg: Volume, _: &mut Context<ConntrackCollector>) -> Self::Result {
+        let Volume(local, since) = msg;
+
+        MessageResult(self.table.connections.get(&local).map(|remotes| {
+            let mut volume = HostVolume {
+                addr: local,
+                since,
+                in_volume: Default::default(),
+                out_volume: Default::default(),
+            };
+            for traffic in remotes.values() {
+                let (in_volume, out_volume) = traffic.volume_since(since);
+                volume.in_volume += &in_volume;
+                volume.out_volume += &out_volume;
+            }
+            volume
+        }))
+    }
+}
+
+impl Handler<Snapshot> for ConntrackCollector {
+    type Result = MessageResult<Snapshot>;
+
+    fn handle(&mut self, _: Snapshot, _: &mut Context<ConntrackCollector>) -> Self::Result {
+        let resolution = chrono::Duration::from_std(common::RESOLUTION).unwrap();
+
+        MessageResult(
+            self.table
+                .connections
+                .iter()
+                .filter_map(|(local, remotes)| {
+                    let in_rates = common::sum_rates(
+                        resolution,
+                        remotes.values().map(|traffic| traffic.snapshot_in_rates()),
+                    )?;
+                    let out_rates = common::sum_rates(
+                        resolution,
+                        remotes.values().map(|traffic| traffic.snapshot_out_rates()),
+                    )?;
+
+                    Some(HostTraffic {
+                        addr: *local,
+                        in_rates: in_rates.into(),
+                        out_rates: out_rates.into(),
+                        remotes: remotes
+                            .iter()
+                            .map(|(remote, traffic)| RemoteRate {
+                                addr: *remote,
+                                in_rate: traffic.current_in_rate(),
+                                out_rate: traffic.current_out_rate(),
+                            })
+                            .collect(),
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Handler<Counters> for ConntrackCollector {
+    type Result = MessageResult<Counters>;
+
+    fn handle(&mut self, _: Counters, _: &mut Context<ConntrackCollector>) -> Self::Result {
+        MessageResult(
+            self.table
+                .hosts
+                .iter()
+                .map(|(local, totals)| HostCounters {
+                    addr: *local,
+                    in_count: totals.in_count,
+                    out_count: totals.out_count,
+                    remotes: self
+                        .table
+                        .connections
+                        .get(local)
+                        .into_iter()
+                        .flatten()
+                        .map(|(remote, traffic)| RemoteCounters {
+                            addr: *remote,
+                            in_count: traffic.last_in(),
+                            out_count: traffic.last_out(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Handler<Subscribe> for ConntrackCollector {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<ConntrackCollector>) {
+        self.subscribers.push(msg);
     }
 }
 
@@ -105,6 +690,375 @@ impl Actor for ConntrackCollector {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.reader = Some(Reader::start(self.source.clone()));
         ctx.notify(Ping);
+        if self.discovery.is_some() {
+            ctx.run_interval(self.config.poll.interfaces, |collector, _| {
+                collector.refresh_networks()
+            });
+        }
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.checkpoint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::source::MemorySource;
+    use crate::minirrd::Archive;
+    use spectral::prelude::*;
+    use std::time::Duration;
+
+    fn flow(sport: u16, bytes: u64) -> String {
+        format!(
+            "ipv4     2 tcp      6 300 ESTABLISHED src=192.168.3.10 dst=1.2.3.4 sport={} dport=443 packets={} bytes={} src=1.2.3.4 dst=192.168.3.10 sport=443 dport={} packets=1 bytes=100 [ASSURED] mark=0 zone=0 use=2\n",
+            sport, bytes / 10, bytes, sport
+        )
+    }
+
+    fn process(table: &mut Table, networks: &LocalNetworks, poll: &str) -> PollStats {
+        TableCollector::process(table, networks, |collector| {
+            parse::parse(poll.as_bytes(), collector, TableCollector::collect)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_missing_accounting() {
+        let mut table = Table::new(vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }]);
+        let networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+
+        let stats = process(&mut table, &networks, "");
+        assert_that(&stats.missing_accounting()).is_none();
+
+        let stats = process(&mut table, &networks, &flow(1000, 1000));
+        assert_that(&stats.flows).is_equal_to(1);
+        assert_that(&stats.missing_accounting()).contains_value(false);
+
+        let poll = "ipv4     2 tcp      6 300 ESTABLISHED src=192.168.3.10 dst=1.2.3.4 sport=1000 dport=443 src=1.2.3.4 dst=192.168.3.10 sport=443 dport=1000 [ASSURED] mark=0 zone=0 use=2\n";
+        let stats = process(&mut table, &networks, poll);
+        assert_that(&stats.missing_accounting()).contains_value(true);
+    }
+
+    #[test]
+    fn test_accounting_enabled_after_startup() {
+        let acct_file = std::env::temp_dir().join(format!("nftracker-acct-{}", std::process::id()));
+        std::fs::write(&acct_file, "0\n").unwrap();
+        let config = toml::from_str::<Config>(&format!(
+            r#"
+            local_subnets = ["192.168.3.0/24"]
+            wan_interface = "eth0"
+            conntrack_acct_file = "{}"
+        "#,
+            acct_file.display()
+        ))
+        .unwrap();
+        let unaccounted = "ipv4     2 tcp      6 300 ESTABLISHED src=192.168.3.10 dst=1.2.3.4 sport=1000 dport=443 src=1.2.3.4 dst=192.168.3.10 sport=443 dport=1000 [ASSURED] mark=0 zone=0 use=2\n";
+        let source = MemorySource::new(Vec::<String>::new());
+        let mut collector = ConntrackCollector::with_discovery(config, Box::new(source), None);
+        std::fs::remove_file(&acct_file).unwrap();
+
+        let startup_warning = collector.poller.health().warning;
+        assert_that(&startup_warning).is_some();
+
+        collector.process_conntrack(unaccounted.as_bytes()).unwrap();
+        assert_that(&collector.poller.health().warning).is_equal_to(startup_warning);
+
+        // The sysctl has been enabled, new flows have counters
+        let accounted = unaccounted.to_string() + &flow(1001, 1000);
+        collector.process_conntrack(accounted.as_bytes()).unwrap();
+        assert_that(&collector.poller.health().warning).is_none();
+    }
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn test_destroyed_flows() {
+        let mut table = Table::new(vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }]);
+        let networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+        let local = "192.168.3.10".parse().unwrap();
+        let events = std::fs::read("fixtures/nf_conntrack.events").unwrap();
+
+        process(&mut table, &networks, "");
+        process(&mut table, &networks, &flow(1000, 1500));
+        // Both flows are closed before the next poll
+        TableCollector::process(&mut table, &networks, |collector| {
+            netlink::parse_events(&events, collector, TableCollector::collect_event)
+        })
+        .unwrap();
+
+        let traffic = &table.connections[&local][&"1.2.3.4".parse().unwrap()];
+        assert_that(&traffic.last_out().bytes).is_equal_to(2000);
+        assert_that(&traffic.last_in().bytes).is_equal_to(1000);
+
+        let traffic = &table.connections[&local][&"8.8.8.8".parse().unwrap()];
+        assert_that(&traffic.last_out().bytes).is_equal_to(60);
+        assert_that(&traffic.last_in().bytes).is_equal_to(120);
+
+        assert_that(&table.flows.is_empty()).is_true();
+    }
+
+    #[test]
+    fn test_discovered_networks() {
+        let config = toml::from_str::<Config>(
+            r#"
+            local_subnets = ["192.168.3.0/24", "iface:br-lan"]
+            wan_interface = "eth0"
+            conntrack_acct_file = "/nonexistent"
+        "#,
+        )
+        .unwrap();
+        let discovery = Discovery::with_sources(
+            Box::new(MemorySource::new(vec![
+                "20010db8abcd00100000000000000001 03 40 00 80   br-lan\n",
+                "20010db8abcd00200000000000000001 03 40 00 80   br-lan\n",
+            ])),
+            Box::new(MemorySource::new(vec!["Iface\tDestination\n"])),
+        );
+        let mut collector = ConntrackCollector::with_discovery(
+            config,
+            Box::new(MemorySource::new(vec![""])),
+            Some(discovery),
+        );
+        let is_local = |collector: &ConntrackCollector, addr: &str| {
+            collector.networks.lookup(&addr.parse().unwrap()).is_some()
+        };
+
+        assert_that(&is_local(&collector, "192.168.3.10")).is_true();
+        assert_that(&is_local(&collector, "2001:db8:abcd:10::5")).is_true();
+        assert_that(&is_local(&collector, "2001:db8:abcd:20::5")).is_false();
+
+        // The delegated prefix has changed
+        collector.refresh_networks();
+
+        assert_that(&is_local(&collector, "192.168.3.10")).is_true();
+        assert_that(&is_local(&collector, "2001:db8:abcd:10::5")).is_false();
+        assert_that(&is_local(&collector, "2001:db8:abcd:20::5")).is_true();
+    }
+
+    #[test]
+    fn test_groups_and_exclusions() {
+        let mut table = Table::new(vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }]);
+        let mut networks = LocalNetworks::new(
+            vec!["192.168.0.0/16".parse().unwrap()],
+            vec!["192.168.3.10/32".parse().unwrap()],
+        );
+        networks.add_group("guests", "192.168.10.0/24".parse().unwrap());
+        let poll = flow(1000, 1000)
+            + &flow(1001, 2000).replace("192.168.3.10", "192.168.10.5")
+            + &flow(1002, 3000).replace("192.168.3.10", "192.168.4.5");
+
+        process(&mut table, &networks, &poll);
+
+        let mut locals = table.connections.keys().cloned().collect::<Vec<_>>();
+        locals.sort();
+        assert_that(&locals).is_equal_to(vec![
+            "192.168.4.5".parse().unwrap(),
+            "192.168.10.5".parse().unwrap(),
+        ]);
+        assert_that(&table.groups.len()).is_equal_to(1);
+        assert_that(&&*table.groups[&"192.168.10.5".parse().unwrap()]).is_equal_to("guests");
+    }
+
+    #[test]
+    fn test_nat_flows() {
+        let mut table = Table::new(vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }]);
+        let networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+        let local = "192.168.3.10".parse().unwrap();
+        // Masqueraded via the WAN address 100.64.0.2
+        let masquerade = "ipv4     2 tcp      6 300 ESTABLISHED src=192.168.3.10 dst=1.2.3.4 sport=1000 dport=443 packets=10 bytes=1000 src=1.2.3.4 dst=100.64.0.2 sport=443 dport=1000 packets=20 bytes=5000 [ASSURED] mark=0 zone=0 use=2\n";
+        // Port 8080 of the WAN address forwarded to port 80 of the local host
+        let port_forward = "ipv4     2 tcp      6 300 ESTABLISHED src=5.6.7.8 dst=100.64.0.2 sport=2000 dport=8080 packets=3 bytes=300 src=192.168.3.10 dst=5.6.7.8 sport=80 dport=2000 packets=7 bytes=7000 [ASSURED] mark=0 zone=0 use=2\n";
+        // Both ends are local, only the initiating host is credited
+        let internal = "ipv4     2 tcp      6 300 ESTABLISHED src=192.168.3.10 dst=192.168.3.20 sport=1001 dport=22 packets=4 bytes=400 src=192.168.3.20 dst=192.168.3.10 sport=22 dport=1001 packets=8 bytes=800 [ASSURED] mark=0 zone=0 use=2\n";
+
+        process(&mut table, &networks, "");
+        let stats = process(
+            &mut table,
+            &networks,
+            &format!("{}{}{}", masquerade, port_forward, internal),
+        );
+        assert_that(&stats.flows).is_equal_to(3);
+
+        let traffic = &table.connections[&local][&"1.2.3.4".parse().unwrap()];
+        assert_that(&traffic.last_out().bytes).is_equal_to(1000);
+        assert_that(&traffic.last_in().bytes).is_equal_to(5000);
+
+        let traffic = &table.connections[&local][&"5.6.7.8".parse().unwrap()];
+        assert_that(&traffic.last_out().bytes).is_equal_to(7000);
+        assert_that(&traffic.last_in().bytes).is_equal_to(300);
+
+        let traffic = &table.connections[&local][&"192.168.3.20".parse().unwrap()];
+        assert_that(&traffic.last_out().bytes).is_equal_to(400);
+        assert_that(&traffic.last_in().bytes).is_equal_to(800);
+
+        assert_that(&table.connections.len()).is_equal_to(1);
+        assert_that(&table.connections[&local].len()).is_equal_to(3);
+    }
+
+    #[test]
+    fn test_accumulate_flows() {
+        let mut table = Table::new(vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }]);
+        let networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+        let local = "192.168.3.10".parse().unwrap();
+        let remote = "1.2.3.4".parse().unwrap();
+
+        process(&mut table, &networks, "");
+        // Two parallel flows
+        let poll = flow(1000, 1000) + &flow(1001, 2000);
+        process(&mut table, &networks, &poll);
+        // First one progresses, second one is closed, a new one is opened
+        let poll = flow(1000, 1500) + &flow(1002, 500);
+        process(&mut table, &networks, &poll);
+        // Port of the first flow is recycled with a new connection
+        let poll = flow(1000, 300) + &flow(1002, 500);
+        process(&mut table, &networks, &poll);
+
+        let traffic = &table.connections[&local][&remote];
+
+        assert_that(&traffic.last_out().bytes).is_equal_to(1000 + 2000 + 500 + 500 + 300);
+        assert_that(&traffic.last_out().packets).is_equal_to(100 + 200 + 50 + 50 + 30);
+        // The replies are constant, so only new ports count
+        assert_that(&traffic.last_in().bytes).is_equal_to(300);
+        assert_that(&table.flows.len()).is_equal_to(4);
+    }
+
+    #[test]
+    fn test_baseline_poll() {
+        let mut table = Table::new(vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }]);
+        let networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+        let local = "192.168.3.10".parse().unwrap();
+        let remote = "1.2.3.4".parse().unwrap();
+
+        // Flows already open at startup only count from the first poll on
+        process(&mut table, &networks, &flow(1000, 100_000));
+        assert_that(&table.connections[&local][&remote].last_out().bytes).is_equal_to(0);
+
+        process(
+            &mut table,
+            &networks,
+            &(flow(1000, 100_500) + &flow(1001, 200)),
+        );
+        assert_that(&table.connections[&local][&remote].last_out().bytes).is_equal_to(700);
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut table = Table::new(vec![
+            Archive {
+                resolution: common::RESOLUTION,
+                retain: Duration::from_secs(60),
+            },
+            Archive {
+                resolution: Duration::from_secs(60),
+                retain: Duration::from_secs(3600),
+            },
+        ]);
+        let networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+        let local = "192.168.3.10".parse::<Local>().unwrap();
+        let at = |secs: i64| Utc::now().naive_utc() + chrono::Duration::seconds(secs);
+        let to = |remote: &str| flow(1000, 1000).replace("1.2.3.4", remote);
+
+        process(&mut table, &networks, "");
+        process(&mut table, &networks, &(to("1.1.1.1") + &to("2.2.2.2")));
+        // The host goes quiet, its totals are kept
+        process(&mut table, &networks, "");
+        table.expire(at(0), 10);
+        assert_that(&table.connections[&local].len()).is_equal_to(2);
+        assert_that(
+            &table.connections[&local][&"1.1.1.1".parse().unwrap()]
+                .last_out()
+                .bytes,
+        )
+        .is_equal_to(1000);
+
+        // Only the most recently seen remotes are kept
+        process(&mut table, &networks, &to("3.3.3.3"));
+        table.expire(at(0), 1);
+        assert_that(&table.connections[&local].keys().collect::<Vec<_>>())
+            .is_equal_to(vec![&"3.3.3.3".parse::<Remote>().unwrap()]);
+        // The host totals still include the expired remotes
+        assert_that(&table.hosts[&local].out_count.bytes).is_equal_to(3000);
+        assert_that(&table.hosts[&local].in_count.bytes).is_equal_to(300);
+
+        // Idle for longer than the coarsest archive
+        table.expire(at(3601), 10);
+        assert_that(&table.connections.is_empty()).is_true();
+        assert_that(&table.seen.is_empty()).is_true();
+        assert_that(&table.hosts[&local].out_count.bytes).is_equal_to(3000);
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let archives = vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }];
+        let mut table = Table::new(archives.clone());
+        let mut networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+        networks.add_group("lan", "192.168.3.0/24".parse().unwrap());
+        let local = "192.168.3.10".parse::<Local>().unwrap();
+
+        process(&mut table, &networks, "");
+        process(&mut table, &networks, &flow(1000, 1000));
+        let mut checkpoint = vec![];
+        table.write_to(&mut checkpoint).unwrap();
+        let restored = Table::read_from(&mut checkpoint.as_slice(), archives).unwrap();
+
+        assert_that(&restored.connections[&local].len()).is_equal_to(1);
+        assert_that(&restored.hosts[&local].out_count.bytes).is_equal_to(1000);
+        assert_that(&restored.group_totals["lan"].in_count.bytes).is_equal_to(100);
+        assert_that(&restored.flows.len()).is_equal_to(2);
+        assert_that(&restored.first_poll).is_false();
+    }
+
+    #[test]
+    fn test_restored_baseline() {
+        let archives = vec![Archive {
+            resolution: common::RESOLUTION,
+            retain: Duration::from_secs(60),
+        }];
+        let mut table = Table::new(archives.clone());
+        let networks = LocalNetworks::new(vec!["192.168.3.0/24".parse().unwrap()], vec![]);
+        let local = "192.168.3.10".parse().unwrap();
+        let remote = "1.2.3.4".parse().unwrap();
+
+        process(&mut table, &networks, &flow(1000, 100_000));
+        let mut checkpoint = vec![];
+        table.write_to(&mut checkpoint).unwrap();
+        let mut table = Table::read_from(&mut checkpoint.as_slice(), archives.clone()).unwrap();
+
+        // The traffic during the restart is counted against the restored flows
+        process(&mut table, &networks, &flow(1000, 100_500));
+        assert_that(&table.connections[&local][&remote].last_out().bytes).is_equal_to(500);
+
+        // Without any restored flow the first poll is a baseline again
+        let mut checkpoint = vec![];
+        Table::new(archives.clone())
+            .write_to(&mut checkpoint)
+            .unwrap();
+        let mut table = Table::read_from(&mut checkpoint.as_slice(), archives).unwrap();
+        process(&mut table, &networks, &flow(1000, 100_000));
+        assert_that(&table.connections[&local][&remote].last_out().bytes).is_equal_to(0);
     }
 }
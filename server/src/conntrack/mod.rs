@@ -1,6 +1,9 @@
 mod collector;
 mod model;
+mod netlink;
 mod parse;
 
-pub use collector::ConntrackCollector;
+pub use collector::{
+    ConntrackCollector, Counters, Groups, Health, Hosts, Remotes, Snapshot, Volume,
+};
 pub use model::*;
@@ -1,58 +1,284 @@
-use crate::common::Trafic;
+use super::parse::{ConntrackEntry, ConntrackTuple};
+use crate::common::{TrafficCounter, Trafic};
+use crate::minirrd::{Archive, Persist};
 use chrono::NaiveDateTime;
 use std::collections::HashMap;
+use std::io::{self, Read, Write};
 use std::net::IpAddr;
-use std::time::Duration;
+use std::rc::Rc;
 
 pub type Local = IpAddr;
 pub type Remote = IpAddr;
 
+/// Identity of one direction of a conntrack entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub zone: u16,
+    pub src: IpAddr,
+    pub sport: u16,
+    pub dst: IpAddr,
+    pub dport: u16,
+}
+
+impl FlowKey {
+    /// Key of one direction of a flow, the counters of both directions are tracked separately
+    pub fn new(entry: &ConntrackEntry, tuple: &ConntrackTuple) -> Self {
+        FlowKey {
+            protocol: entry.protocol_number,
+            zone: entry.zone,
+            src: tuple.src,
+            sport: tuple.sport,
+            dst: tuple.dst,
+            dport: tuple.dport,
+        }
+    }
+}
+
+impl Persist for FlowKey {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.protocol.write_to(out)?;
+        self.zone.write_to(out)?;
+        self.src.write_to(out)?;
+        self.sport.write_to(out)?;
+        self.dst.write_to(out)?;
+        self.dport.write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(FlowKey {
+            protocol: u8::read_from(input)?,
+            zone: u16::read_from(input)?,
+            src: IpAddr::read_from(input)?,
+            sport: u16::read_from(input)?,
+            dst: IpAddr::read_from(input)?,
+            dport: u16::read_from(input)?,
+        })
+    }
+}
+
+/// Total traffic of a local host or group since it was first seen
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Totals {
+    pub in_count: TrafficCounter,
+    pub out_count: TrafficCounter,
+}
+
+impl Persist for Totals {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.in_count.write_to(out)?;
+        self.out_count.write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(Totals {
+            in_count: TrafficCounter::read_from(input)?,
+            out_count: TrafficCounter::read_from(input)?,
+        })
+    }
+}
+
+/// The traffic of each (local, remote) pair is the sum over all its flows, including the
+/// ones already closed. Pairs are expired eventually, so only `hosts` and `group_totals`
+/// are guaranteed to only ever grow.
 #[derive(Debug)]
 pub struct Table {
-    retain: Duration,
+    archives: Vec<Archive>,
     pub connections: HashMap<Local, HashMap<Remote, Trafic>>,
+    /// Totals of every local host, not affected by expiring its remotes
+    pub hosts: HashMap<Local, Totals>,
+    /// Totals of every named subnet group
+    pub group_totals: HashMap<Rc<str>, Totals>,
+    /// Counters of all flows at the last poll
+    pub flows: HashMap<FlowKey, TrafficCounter>,
+    /// Local hosts in a named subnet
+    pub groups: HashMap<Local, Rc<str>>,
+    /// Last time a pair had any flow, pairs restored from a checkpoint count as seen at startup
+    pub seen: HashMap<(Local, Remote), NaiveDateTime>,
+    /// Nothing has been polled or restored since startup, i.e. `flows` is not a baseline yet
+    pub first_poll: bool,
 }
 
 impl Table {
-    pub fn new(retain: Duration) -> Table {
+    pub fn new(archives: Vec<Archive>) -> Table {
         Table {
-            retain,
+            archives,
             connections: HashMap::new(),
+            hosts: HashMap::new(),
+            group_totals: HashMap::new(),
+            flows: HashMap::new(),
+            groups: HashMap::new(),
+            seen: HashMap::new(),
+            first_poll: true,
         }
     }
 
-    pub fn push_in(
+    /// Forget pairs without any flow for longer than the coarsest archive retains and, beyond
+    /// `max_remotes`, the least recently seen remotes of a local host. `hosts` and
+    /// `group_totals` keep their traffic.
+    pub fn expire(&mut self, now: NaiveDateTime, max_remotes: usize) {
+        let expired_before = self
+            .archives
+            .iter()
+            .map(|archive| archive.retain)
+            .max()
+            .and_then(|retain| chrono::Duration::from_std(retain).ok())
+            .and_then(|retain| now.checked_sub_signed(retain));
+        let seen = &mut self.seen;
+
+        for (local, remotes) in self.connections.iter_mut() {
+            let mut ages = remotes
+                .keys()
+                .map(|remote| (*seen.entry((*local, *remote)).or_insert(now), *remote))
+                .collect::<Vec<_>>();
+            let excess = ages.len().saturating_sub(max_remotes);
+
+            if excess > 0 {
+                // Least recently seen first
+                ages.sort_unstable();
+            }
+            for (index, (last_seen, remote)) in ages.into_iter().enumerate() {
+                if index < excess || expired_before.is_some_and(|before| last_seen < before) {
+                    remotes.remove(&remote);
+                    seen.remove(&(*local, remote));
+                }
+            }
+        }
+        self.connections.retain(|_, remotes| !remotes.is_empty());
+
+        let connections = &self.connections;
+        self.groups
+            .retain(|local, _| connections.contains_key(local));
+    }
+
+    /// Carry the current totals of all pairs forward, so that idle pairs drop to a rate of 0
+    pub fn tick(&mut self, timestamp: NaiveDateTime) {
+        for traffic in self.connections.values_mut().flat_map(HashMap::values_mut) {
+            let (last_in, last_out) = (traffic.last_in(), traffic.last_out());
+
+            traffic.put_in(timestamp, last_in.bytes, last_in.packets);
+            traffic.put_out(timestamp, last_out.bytes, last_out.packets);
+        }
+    }
+
+    pub fn add_in(
         &mut self,
         timestamp: NaiveDateTime,
         local: IpAddr,
         remote: IpAddr,
-        bytes: u64,
-        packets: u64,
+        delta: &TrafficCounter,
     ) {
-        let trafic = self.upsert_timeseries(local, remote);
-        trafic.put_in(timestamp, bytes, packets);
+        let traffic = self.upsert_timeseries(local, remote);
+        let mut total = traffic.last_in();
+
+        total += delta;
+        traffic.put_in(timestamp, total.bytes, total.packets);
+        for totals in self.totals(local) {
+            totals.in_count += delta;
+        }
     }
 
-    pub fn push_out(
+    pub fn add_out(
         &mut self,
         timestamp: NaiveDateTime,
         local: IpAddr,
         remote: IpAddr,
-        bytes: u64,
-        packets: u64,
+        delta: &TrafficCounter,
     ) {
         let traffic = self.upsert_timeseries(local, remote);
-        traffic.put_out(timestamp, bytes, packets);
+        let mut total = traffic.last_out();
+
+        total += delta;
+        traffic.put_out(timestamp, total.bytes, total.packets);
+        for totals in self.totals(local) {
+            totals.out_count += delta;
+        }
+    }
+
+    /// Totals of a local host and its group, if it has one
+    fn totals(&mut self, local: IpAddr) -> impl Iterator<Item = &mut Totals> {
+        let group_totals = &mut self.group_totals;
+        let group = self
+            .groups
+            .get(&local)
+            .map(move |group| group_totals.entry(group.clone()).or_default());
+
+        std::iter::once(self.hosts.entry(local).or_default()).chain(group)
+    }
+
+    fn upsert_timeseries(&mut self, local: IpAddr, remote: IpAddr) -> &mut Trafic {
+        let archives = &self.archives;
+        self.connections
+            .entry(local)
+            .or_default()
+            .entry(remote)
+            .or_insert_with(|| Trafic::new(archives))
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (self.connections.len() as u64).write_to(out)?;
+        for (local, remotes) in &self.connections {
+            local.write_to(out)?;
+            (remotes.len() as u64).write_to(out)?;
+            for (remote, traffic) in remotes {
+                remote.write_to(out)?;
+                traffic.write_to(out)?;
+            }
+        }
+        (self.hosts.len() as u64).write_to(out)?;
+        for (local, totals) in &self.hosts {
+            local.write_to(out)?;
+            totals.write_to(out)?;
+        }
+        (self.group_totals.len() as u64).write_to(out)?;
+        for (group, totals) in &self.group_totals {
+            group.to_string().write_to(out)?;
+            totals.write_to(out)?;
+        }
+        (self.flows.len() as u64).write_to(out)?;
+        for (flow, counter) in &self.flows {
+            flow.write_to(out)?;
+            counter.write_to(out)?;
+        }
+        Ok(())
     }
 
-    fn upsert_timeseries<'a>(&'a mut self, local: IpAddr, remote: IpAddr) -> &'a mut Trafic {
-        if !self.connections.contains_key(&local) {
-            self.connections.insert(local, HashMap::new());
+    pub fn read_from<R: Read>(input: &mut R, archives: Vec<Archive>) -> io::Result<Table> {
+        let mut table = Table::new(archives);
+
+        for _ in 0..u64::read_from(input)? {
+            let local = IpAddr::read_from(input)?;
+            let mut remotes = HashMap::new();
+
+            for _ in 0..u64::read_from(input)? {
+                let remote = IpAddr::read_from(input)?;
+                let traffic = Trafic::read_from(input, &table.archives)?;
+
+                remotes.insert(remote, traffic);
+            }
+            table.connections.insert(local, remotes);
         }
-        let remotes = self.connections.get_mut(&local).unwrap();
-        if !remotes.contains_key(&remote) {
-            remotes.insert(remote, Trafic::new(self.retain));
+        for _ in 0..u64::read_from(input)? {
+            let local = IpAddr::read_from(input)?;
+
+            table.hosts.insert(local, Totals::read_from(input)?);
+        }
+        for _ in 0..u64::read_from(input)? {
+            let group = String::read_from(input)?;
+
+            table
+                .group_totals
+                .insert(group.into(), Totals::read_from(input)?);
         }
-        remotes.get_mut(&remote).unwrap()
+        for _ in 0..u64::read_from(input)? {
+            let flow = FlowKey::read_from(input)?;
+            let counter = TrafficCounter::read_from(input)?;
+
+            table.flows.insert(flow, counter);
+        }
+        // The restored flows are the baseline, so traffic during a short restart is counted
+        table.first_poll = table.flows.is_empty();
+
+        Ok(table)
     }
 }
@@ -0,0 +1,512 @@
+use super::parse::ConntrackEntry;
+use crate::common::source::Source;
+use log::{error, warn};
+use nom::bytes::complete::take;
+use nom::error::{ErrorKind, ParseError, VerboseError};
+use nom::multi::many0;
+use nom::number::complete::{be_u16, be_u32, be_u64, be_u8};
+use nom::{Err, IResult};
+use std::io::{self, Cursor, Read};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::RawFd;
+
+// Netlink headers are in host byte order, the conntrack attributes in network byte order
+#[cfg(target_endian = "big")]
+use nom::number::complete::{be_u16 as ne_u16, be_u32 as ne_u32};
+#[cfg(target_endian = "little")]
+use nom::number::complete::{le_u16 as ne_u16, le_u32 as ne_u32};
+
+const NLMSG_HDRLEN: usize = 16;
+const NFNL_SUBSYS_CTNETLINK: u16 = 1;
+const IPCTNL_MSG_CT_NEW: u16 = 0;
+const IPCTNL_MSG_CT_GET: u16 = 1;
+const IPCTNL_MSG_CT_DELETE: u16 = 2;
+
+const NFNLGRP_CONNTRACK_DESTROY: u32 = 3;
+
+const CTA_TUPLE_ORIG: u16 = 1;
+const CTA_TUPLE_REPLY: u16 = 2;
+const CTA_COUNTERS_ORIG: u16 = 9;
+const CTA_COUNTERS_REPLY: u16 = 10;
+const CTA_ZONE: u16 = 18;
+
+const CTA_TUPLE_IP: u16 = 1;
+const CTA_TUPLE_PROTO: u16 = 2;
+
+const CTA_IP_V4_SRC: u16 = 1;
+const CTA_IP_V4_DST: u16 = 2;
+const CTA_IP_V6_SRC: u16 = 3;
+const CTA_IP_V6_DST: u16 = 4;
+
+const CTA_PROTO_NUM: u16 = 1;
+const CTA_PROTO_SRC_PORT: u16 = 2;
+const CTA_PROTO_DST_PORT: u16 = 3;
+
+const CTA_COUNTERS_PACKETS: u16 = 1;
+const CTA_COUNTERS_BYTES: u16 = 2;
+const CTA_COUNTERS32_PACKETS: u16 = 3;
+const CTA_COUNTERS32_BYTES: u16 = 4;
+
+type TypedPayload<'a> = (u16, &'a [u8]);
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn message<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], TypedPayload<'a>, E> {
+    let (rest, len) = ne_u32(input)?;
+    let (rest, msg_type) = ne_u16(rest)?;
+    let (rest, _flags) = ne_u16(rest)?;
+    let (rest, _seq) = ne_u32(rest)?;
+    let (rest, _pid) = ne_u32(rest)?;
+
+    if (len as usize) < NLMSG_HDRLEN || len as usize > input.len() {
+        return Err(Err::Error(E::from_error_kind(
+            input,
+            ErrorKind::LengthValue,
+        )));
+    }
+    let (_, payload) = take(len as usize - NLMSG_HDRLEN)(rest)?;
+    let next = align(len as usize).min(input.len());
+
+    Ok((&input[next..], (msg_type, payload)))
+}
+
+fn attribute<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], TypedPayload<'a>, E> {
+    let (rest, len) = ne_u16(input)?;
+    let (rest, attr_type) = ne_u16(rest)?;
+
+    if (len as usize) < 4 || len as usize > input.len() {
+        return Err(Err::Error(E::from_error_kind(
+            input,
+            ErrorKind::LengthValue,
+        )));
+    }
+    let (_, payload) = take(len as usize - 4)(rest)?;
+    let next = align(len as usize).min(input.len());
+
+    // Strip NLA_F_NESTED and NLA_F_NET_BYTEORDER
+    Ok((&input[next..], (attr_type & 0x3fff, payload)))
+}
+
+fn attributes<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], Vec<TypedPayload<'a>>, E> {
+    many0(attribute)(input)
+}
+
+fn ip_addr(payload: &[u8]) -> Option<IpAddr> {
+    match payload.len() {
+        4 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(payload);
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(payload);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+fn decode_tuple(entry: &mut ConntrackEntry, reply: bool, payload: &[u8]) -> Option<()> {
+    let tuple = if reply {
+        &mut entry.reply
+    } else {
+        &mut entry.original
+    };
+    let (_, attrs) = attributes::<VerboseError<&[u8]>>(payload).ok()?;
+
+    for (attr_type, payload) in attrs {
+        match attr_type {
+            CTA_TUPLE_IP => {
+                for (ip_type, payload) in attributes::<VerboseError<&[u8]>>(payload).ok()?.1 {
+                    match ip_type {
+                        CTA_IP_V4_SRC | CTA_IP_V6_SRC => tuple.src = ip_addr(payload)?,
+                        CTA_IP_V4_DST | CTA_IP_V6_DST => tuple.dst = ip_addr(payload)?,
+                        _ => (),
+                    }
+                }
+            }
+            CTA_TUPLE_PROTO => {
+                for (proto_type, payload) in attributes::<VerboseError<&[u8]>>(payload).ok()?.1 {
+                    match proto_type {
+                        CTA_PROTO_NUM => {
+                            entry.protocol_number = be_u8::<VerboseError<&[u8]>>(payload).ok()?.1;
+                        }
+                        CTA_PROTO_SRC_PORT => {
+                            tuple.sport = be_u16::<VerboseError<&[u8]>>(payload).ok()?.1
+                        }
+                        CTA_PROTO_DST_PORT => {
+                            tuple.dport = be_u16::<VerboseError<&[u8]>>(payload).ok()?.1
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Some(())
+}
+
+fn decode_counters(entry: &mut ConntrackEntry, reply: bool, payload: &[u8]) -> Option<()> {
+    let tuple = if reply {
+        &mut entry.reply
+    } else {
+        &mut entry.original
+    };
+
+    for (attr_type, payload) in attributes::<VerboseError<&[u8]>>(payload).ok()?.1 {
+        match attr_type {
+            CTA_COUNTERS_PACKETS => tuple.packets = be_u64::<VerboseError<&[u8]>>(payload).ok()?.1,
+            CTA_COUNTERS_BYTES => {
+                tuple.bytes = be_u64::<VerboseError<&[u8]>>(payload).ok()?.1;
+                entry.accounting = true;
+            }
+            CTA_COUNTERS32_PACKETS => {
+                tuple.packets = u64::from(be_u32::<VerboseError<&[u8]>>(payload).ok()?.1)
+            }
+            CTA_COUNTERS32_BYTES => {
+                tuple.bytes = u64::from(be_u32::<VerboseError<&[u8]>>(payload).ok()?.1);
+                entry.accounting = true;
+            }
+            _ => (),
+        }
+    }
+    Some(())
+}
+
+/// Decode a conntrack message into a flow with its original and reply tuple
+fn decode_flow(payload: &[u8]) -> Option<ConntrackEntry> {
+    let (_, attrs) = attributes::<VerboseError<&[u8]>>(payload.get(4..)?).ok()?;
+    let mut entry = ConntrackEntry {
+        zone: attrs
+            .iter()
+            .find(|(attr_type, _)| *attr_type == CTA_ZONE)
+            .and_then(|(_, payload)| be_u16::<VerboseError<&[u8]>>(payload).ok())
+            .map(|(_, zone)| zone)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+
+    for (attr_type, payload) in attrs {
+        match attr_type {
+            CTA_TUPLE_ORIG => decode_tuple(&mut entry, false, payload)?,
+            CTA_TUPLE_REPLY => decode_tuple(&mut entry, true, payload)?,
+            CTA_COUNTERS_ORIG => decode_counters(&mut entry, false, payload)?,
+            CTA_COUNTERS_REPLY => decode_counters(&mut entry, true, payload)?,
+            _ => (),
+        }
+    }
+    Some(entry)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    New,
+    Destroy,
+}
+
+/// Visit all conntrack messages of a sequence of netlink messages
+fn visit_messages<V, C>(input: &[u8], mut initial: C, visitor: V) -> io::Result<C>
+where
+    V: Fn(C, Event, &ConntrackEntry) -> C,
+{
+    let mut input = input;
+
+    while !input.is_empty() {
+        let (rest, (msg_type, payload)) = match message::<VerboseError<&[u8]>>(input) {
+            Ok(result) => result,
+            Err(error) => {
+                error!("Invalid netlink message: {:?}", error);
+                break;
+            }
+        };
+        input = rest;
+
+        let event = match msg_type as i32 {
+            libc::NLMSG_DONE => break,
+            libc::NLMSG_ERROR => {
+                let (_, errno) = ne_u32::<VerboseError<&[u8]>>(payload)
+                    .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+                if errno != 0 {
+                    return Err(io::Error::from_raw_os_error(-(errno as i32)));
+                }
+                continue;
+            }
+            _ if msg_type == NFNL_SUBSYS_CTNETLINK << 8 | IPCTNL_MSG_CT_NEW => Event::New,
+            _ if msg_type == NFNL_SUBSYS_CTNETLINK << 8 | IPCTNL_MSG_CT_DELETE => Event::Destroy,
+            _ => continue,
+        };
+        match decode_flow(payload) {
+            Some(entry) => initial = visitor(initial, event, &entry),
+            None => error!("Invalid conntrack message: {:?}", payload),
+        }
+    }
+
+    Ok(initial)
+}
+
+/// Visit all flows of a netlink dump
+pub fn parse<V, C>(input: &[u8], initial: C, visitor: V) -> io::Result<C>
+where
+    V: Fn(C, &ConntrackEntry) -> C,
+{
+    visit_messages(input, initial, |acc, event, entry| match event {
+        Event::New => visitor(acc, entry),
+        Event::Destroy => acc,
+    })
+}
+
+/// Visit all flows of a sequence of conntrack events
+pub fn parse_events<V, C>(input: &[u8], initial: C, visitor: V) -> io::Result<C>
+where
+    V: Fn(C, Event, &ConntrackEntry) -> C,
+{
+    visit_messages(input, initial, visitor)
+}
+
+struct Socket(RawFd);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+impl Socket {
+    fn open(flags: libc::c_int) -> io::Result<Socket> {
+        let socket = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags,
+                libc::NETLINK_NETFILTER,
+            )
+        };
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Socket(socket))
+    }
+
+    fn recv(&self, buf: &mut [u8], flags: libc::c_int) -> io::Result<usize> {
+        let received = unsafe {
+            libc::recv(
+                self.0,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                flags,
+            )
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(received as usize)
+    }
+}
+
+fn is_last(datagram: &[u8]) -> bool {
+    let mut input = datagram;
+
+    while let Ok((rest, (msg_type, _))) = message::<VerboseError<&[u8]>>(input) {
+        if msg_type as i32 == libc::NLMSG_DONE || msg_type as i32 == libc::NLMSG_ERROR {
+            return true;
+        }
+        input = rest;
+    }
+    false
+}
+
+/// Dump the whole conntrack table (all address families) via NETLINK_NETFILTER
+pub fn dump() -> io::Result<Vec<u8>> {
+    let socket = Socket::open(0)?;
+
+    let mut request = Vec::with_capacity(NLMSG_HDRLEN + 4);
+    request.extend_from_slice(&((NLMSG_HDRLEN + 4) as u32).to_ne_bytes());
+    request.extend_from_slice(&(NFNL_SUBSYS_CTNETLINK << 8 | IPCTNL_MSG_CT_GET).to_ne_bytes());
+    request.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    request.extend_from_slice(&1u32.to_ne_bytes());
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    // nfgenmsg: AF_UNSPEC, NFNETLINK_V0, res_id
+    request.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+
+    let mut kernel: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    kernel.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    let sent = unsafe {
+        libc::sendto(
+            socket.0,
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+            &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut dump = Vec::new();
+    let mut buf = vec![0u8; 65536];
+    loop {
+        let received = socket.recv(&mut buf, 0)?;
+        let datagram = &buf[..received];
+        dump.extend_from_slice(datagram);
+        if received == 0 || is_last(datagram) {
+            break;
+        }
+    }
+
+    Ok(dump)
+}
+
+pub struct NetlinkSource;
+
+impl Source for NetlinkSource {
+    fn read(&self) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(Cursor::new(dump()?)))
+    }
+
+    fn describe(&self) -> String {
+        "netlink".to_string()
+    }
+}
+
+/// Non-blocking subscription to the conntrack DESTROY events.
+/// NEW events are not subscribed, since they do not carry any counters yet.
+pub struct Events(Socket);
+
+impl Events {
+    pub fn subscribe() -> io::Result<Events> {
+        let socket = Socket::open(libc::SOCK_NONBLOCK)?;
+        let group = NFNLGRP_CONNTRACK_DESTROY;
+        let result = unsafe {
+            libc::setsockopt(
+                socket.0,
+                libc::SOL_NETLINK,
+                libc::NETLINK_ADD_MEMBERSHIP,
+                &group as *const u32 as *const libc::c_void,
+                mem::size_of::<u32>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Events(socket))
+    }
+
+    /// Receive all pending events
+    pub fn receive(&self) -> io::Result<Vec<u8>> {
+        let mut events = Vec::new();
+        let mut buf = vec![0u8; 65536];
+        loop {
+            match self.0.recv(&mut buf, 0) {
+                Ok(0) => break,
+                Ok(received) => events.extend_from_slice(&buf[..received]),
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref error) if error.raw_os_error() == Some(libc::ENOBUFS) => {
+                    warn!("Conntrack events lost, receive buffer overrun")
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectral::prelude::*;
+    use std::fs;
+
+    // Captured on a little endian machine, the netlink headers are in host byte order
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn test_parse_netlink_dump() {
+        let dump = fs::read("fixtures/nf_conntrack.netlink").unwrap();
+        let entries = parse(&dump, vec![], |mut entries, entry| {
+            entries.push(*entry);
+            entries
+        })
+        .unwrap();
+
+        assert_that(&entries.len()).is_equal_to(3);
+
+        let first = entries[0];
+        assert_that(&first.protocol_number).is_equal_to(17);
+        assert_that(&first.accounting).is_true();
+        assert_that(&first.original.src).is_equal_to("192.168.3.56".parse::<IpAddr>().unwrap());
+        assert_that(&first.original.dst).is_equal_to("192.168.3.1".parse::<IpAddr>().unwrap());
+        assert_that(&first.original.sport).is_equal_to(51556);
+        assert_that(&first.original.dport).is_equal_to(53);
+        assert_that(&first.original.packets).is_equal_to(2);
+        assert_that(&first.original.bytes).is_equal_to(142);
+        assert_that(&first.reply.src).is_equal_to("192.168.3.1".parse::<IpAddr>().unwrap());
+        assert_that(&first.reply.sport).is_equal_to(53);
+        assert_that(&first.reply.bytes).is_equal_to(416);
+
+        let ipv6 = entries[1];
+        assert_that(&ipv6.protocol_number).is_equal_to(6);
+        assert_that(&ipv6.original.src).is_equal_to("1234::15c".parse::<IpAddr>().unwrap());
+        assert_that(&ipv6.original.bytes).is_equal_to(467);
+
+        assert_that(
+            &entries
+                .iter()
+                .map(|entry| entry.original.bytes + entry.reply.bytes)
+                .sum::<u64>(),
+        )
+        .is_equal_to(142 + 416 + 467 + 578 + 4348 + 5489);
+    }
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn test_parse_events() {
+        let events = fs::read("fixtures/nf_conntrack.events").unwrap();
+        let entries = parse_events(&events, vec![], |mut entries, event, entry| {
+            entries.push((event, *entry));
+            entries
+        })
+        .unwrap();
+
+        assert_that(&entries.iter().map(|(event, _)| *event).collect::<Vec<_>>())
+            .is_equal_to(vec![Event::New, Event::Destroy, Event::Destroy]);
+
+        let (_, destroyed) = entries[1];
+        assert_that(&destroyed.protocol_number).is_equal_to(17);
+        assert_that(&destroyed.original.src).is_equal_to("192.168.3.10".parse::<IpAddr>().unwrap());
+        assert_that(&destroyed.original.dport).is_equal_to(53);
+        assert_that(&destroyed.original.packets).is_equal_to(1);
+        assert_that(&destroyed.original.bytes).is_equal_to(60);
+        assert_that(&destroyed.reply.bytes).is_equal_to(120);
+
+        // A dump only reports the current flows
+        assert_that(&parse(&events, 0, |count, _| count + 1).unwrap()).is_equal_to(1);
+    }
+
+    #[test]
+    fn test_parse_error_message() {
+        let mut message = vec![];
+        message.extend_from_slice(&36u32.to_ne_bytes());
+        message.extend_from_slice(&(libc::NLMSG_ERROR as u16).to_ne_bytes());
+        message.extend_from_slice(&[0u8; 10]);
+        message.extend_from_slice(&(-libc::EPERM).to_ne_bytes());
+        message.extend_from_slice(&[0u8; 16]);
+
+        let result = parse(&message, 0, |count, _| count + 1);
+
+        assert_that(&result.map_err(|error| error.raw_os_error()))
+            .is_err_containing(Some(libc::EPERM));
+    }
+}
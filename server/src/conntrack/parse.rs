@@ -1,5 +1,4 @@
 use crate::common::parse::ip_addr;
-use crate::minivec::MiniVec;
 use log::error;
 use nom::branch::alt;
 use nom::bytes::complete::is_not;
@@ -12,11 +11,9 @@ use nom::{Err, IResult};
 use std::io::{self, BufRead, BufReader, Read};
 use std::net::{IpAddr, Ipv4Addr};
 
+/// One direction of a flow with the traffic sent in that direction
 #[derive(Debug, Clone, Copy)]
-pub struct ConntrackEntry<'a> {
-    pub transport: &'a str,
-    pub protocol: &'a str,
-    pub timeout: u64,
+pub struct ConntrackTuple {
     pub src: IpAddr,
     pub sport: u16,
     pub dst: IpAddr,
@@ -25,12 +22,9 @@ pub struct ConntrackEntry<'a> {
     pub packets: u64,
 }
 
-impl<'a> Default for ConntrackEntry<'a> {
+impl Default for ConntrackTuple {
     fn default() -> Self {
-        ConntrackEntry {
-            transport: Default::default(),
-            protocol: Default::default(),
-            timeout: Default::default(),
+        ConntrackTuple {
             src: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             sport: Default::default(),
             dst: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
@@ -41,6 +35,18 @@ impl<'a> Default for ConntrackEntry<'a> {
     }
 }
 
+/// A flow as tracked by conntrack. With NAT the reply tuple differs from the
+/// inverted original tuple, e.g. its `dst` is the WAN address for masqueraded flows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConntrackEntry {
+    pub protocol_number: u8,
+    pub zone: u16,
+    /// Byte/packet counters are only present if nf_conntrack_acct is enabled
+    pub accounting: bool,
+    pub original: ConntrackTuple,
+    pub reply: ConntrackTuple,
+}
+
 fn key_value_pair<'a, O, E: ParseError<&'a str>, F>(
     value_parse: F,
 ) -> impl Fn(&'a str) -> IResult<&'a str, (&'a str, O), E>
@@ -64,10 +70,10 @@ where
 enum Value<'a> {
     Addr(&'a str, IpAddr),
     Number(&'a str, u64),
-    Any(&'a str),
+    Any,
 }
 
-fn key_value<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value, E> {
+fn key_value<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value<'a>, E> {
     alt((
         map(key_value_pair(ip_addr), |(key, value)| {
             Value::Addr(key, value)
@@ -76,64 +82,64 @@ fn key_value<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value,
             key_value_pair(map_res(digit1, str::parse::<u64>)),
             |(key, value)| Value::Number(key, value),
         ),
-        map(is_not(" \t"), Value::Any),
+        map(is_not(" \t"), |_| Value::Any),
     ))(i)
 }
 
-fn parse_line<'a, E: ParseError<&'a str>>(
-    input: &'a str,
-) -> IResult<&'a str, MiniVec<ConntrackEntry<'a>>, E> {
-    let (input, transport) = alphanumeric1(input)?;
+fn parse_line<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ConntrackEntry, E> {
+    // Layer 3 name and number, the addresses tell them anyway
+    let (input, _) = alphanumeric1(input)?;
     let (input, _) = preceded(space1, digit1)(input)?;
-    let (input, protocol) = preceded(space1, alphanumeric1)(input)?;
+    // Name of the protocol, the number identifies it as well
+    let (input, _) = preceded(space1, alphanumeric1)(input)?;
+    let (input, protocol_number) = map_res(preceded(space1, digit1), str::parse::<u8>)(input)?;
+    // Seconds until the entry expires
     let (input, _) = preceded(space1, digit1)(input)?;
-    let (input, timeout) = map_res(preceded(space1, digit1), str::parse::<u64>)(input)?;
     let (input, key_values) = preceded(space1, separated_list(space1, key_value))(input)?;
-    let mut entries: MiniVec<ConntrackEntry<'a>> = Default::default();
-    let mut current = ConntrackEntry {
-        transport,
-        protocol,
-        timeout,
+    let mut entry = ConntrackEntry {
+        protocol_number,
         ..Default::default()
     };
+    // The original tuple is listed first, the reply tuple starts with the second src
+    let mut tuples = 0;
 
     for key_value in key_values {
+        if let Value::Addr("src", _) = key_value {
+            tuples += 1;
+        }
+        let tuple = if tuples > 1 {
+            &mut entry.reply
+        } else {
+            &mut entry.original
+        };
         match key_value {
-            Value::Addr("src", src) => {
-                if !current.src.is_unspecified() {
-                    entries.push(current);
-                    current = ConntrackEntry {
-                        transport,
-                        protocol,
-                        timeout,
-                        ..Default::default()
-                    };
-                }
-                current.src = src;
+            Value::Addr("src", src) => tuple.src = src,
+            Value::Addr("dst", dst) => tuple.dst = dst,
+            Value::Number("sport", sport) => tuple.sport = sport as u16,
+            Value::Number("dport", dport) => tuple.dport = dport as u16,
+            Value::Number("bytes", bytes) => {
+                tuple.bytes = bytes;
+                entry.accounting = true;
             }
-            Value::Addr("dst", dst) => current.dst = dst,
-            Value::Number("sport", sport) => current.sport = sport as u16,
-            Value::Number("dport", dport) => current.dport = dport as u16,
-            Value::Number("bytes", bytes) => current.bytes = bytes,
-            Value::Number("packets", packets) => current.packets = packets,
+            Value::Number("packets", packets) => tuple.packets = packets,
+            Value::Number("zone", zone) => entry.zone = zone as u16,
             _ => (),
         }
     }
-    entries.push(current);
-    Ok((input, entries))
+    Ok((input, entry))
 }
 
 pub fn parse<I, V, C>(input: I, mut initial: C, visitor: V) -> io::Result<C>
 where
     I: Read,
-    V: Fn(C, &ConntrackEntry<'_>) -> C,
+    V: Fn(C, &ConntrackEntry) -> C,
 {
     let buf_reader = BufReader::new(input);
 
     for line_result in buf_reader.lines() {
         let line = line_result?;
         match parse_line::<VerboseError<&str>>(&line) {
-            Ok((_, entries)) => initial = entries.visit(initial, &visitor),
+            Ok((_, entry)) => initial = visitor(initial, &entry),
             Err(error) => {
                 error!("Invalid conntrack entry: {:?}", error);
             }
@@ -152,27 +158,52 @@ mod tests {
 
     #[test]
     fn test_parse_line() {
-        let input = r#"ipv4     2 udp      17 27 src=192.168.3.56 dst=192.168.3.1 sport=51556 dport=53 packets=2 bytes=142 src=192.168.3.1 dst=192.168.3.56 sport=53 dport=51556 packets=2 bytes=416 [ASSURED] mark=0 zone=0 use=2"#;
-        let (remain, mut entries) = parse_line::<VerboseError<&str>>(input).unwrap();
+        let input = r#"ipv4     2 udp      17 27 src=192.168.3.56 dst=192.168.3.1 sport=51556 dport=53 packets=2 bytes=142 src=192.168.3.1 dst=192.168.3.56 sport=53 dport=51556 packets=2 bytes=416 [ASSURED] mark=0 zone=3 use=2"#;
+        let (remain, entry) = parse_line::<VerboseError<&str>>(input).unwrap();
 
         assert_that(&remain).is_equal_to("");
-        assert_that(&entries.len()).is_equal_to(2);
+        assert_that(&entry.protocol_number).is_equal_to(17);
+        assert_that(&entry.zone).is_equal_to(3);
+        assert_that(&entry.accounting).is_true();
+        assert_that(&entry.original.src).is_equal_to("192.168.3.56".parse::<IpAddr>().unwrap());
+        assert_that(&entry.original.dst).is_equal_to("192.168.3.1".parse::<IpAddr>().unwrap());
+        assert_that(&entry.original.sport).is_equal_to(51556);
+        assert_that(&entry.original.bytes).is_equal_to(142);
+        assert_that(&entry.reply.src).is_equal_to("192.168.3.1".parse::<IpAddr>().unwrap());
+        assert_that(&entry.reply.dst).is_equal_to("192.168.3.56".parse::<IpAddr>().unwrap());
+        assert_that(&entry.reply.sport).is_equal_to(53);
+        assert_that(&entry.reply.bytes).is_equal_to(416);
+    }
 
-        let second = entries.pop().unwrap();
-        let first = entries.pop().unwrap();
+    #[test]
+    fn test_parse_line_masquerade() {
+        let input = r#"ipv4     2 tcp      6 431406 ESTABLISHED src=192.168.3.88 dst=1.2.3.9 sport=65107 dport=5223 packets=25 bytes=4348 src=1.2.3.9 dst=192.168.2.101 sport=5223 dport=65107 packets=22 bytes=5489 [ASSURED] mark=0 zone=0 use=2"#;
+        let (_, entry) = parse_line::<VerboseError<&str>>(input).unwrap();
+
+        assert_that(&entry.original.src).is_equal_to("192.168.3.88".parse::<IpAddr>().unwrap());
+        assert_that(&entry.original.packets).is_equal_to(25);
+        assert_that(&entry.reply.src).is_equal_to("1.2.3.9".parse::<IpAddr>().unwrap());
+        assert_that(&entry.reply.dst).is_equal_to("192.168.2.101".parse::<IpAddr>().unwrap());
+        assert_that(&entry.reply.packets).is_equal_to(22);
+        assert_that(&entry.reply.bytes).is_equal_to(5489);
+    }
+
+    #[test]
+    fn test_parse_line_without_accounting() {
+        let input = r#"ipv4     2 tcp      6 431999 ESTABLISHED src=192.168.3.88 dst=1.2.3.9 sport=65107 dport=5223 src=1.2.3.9 dst=192.168.3.88 sport=5223 dport=65107 [ASSURED] mark=0 zone=0 use=2"#;
+        let (_, entry) = parse_line::<VerboseError<&str>>(input).unwrap();
 
-        assert_that(&first.src).is_equal_to("192.168.3.56".parse::<IpAddr>().unwrap());
-        assert_that(&first.dst).is_equal_to("192.168.3.1".parse::<IpAddr>().unwrap());
-        assert_that(&first.bytes).is_equal_to(142);
-        assert_that(&second.src).is_equal_to("192.168.3.1".parse::<IpAddr>().unwrap());
-        assert_that(&second.dst).is_equal_to("192.168.3.56".parse::<IpAddr>().unwrap());
-        assert_that(&second.bytes).is_equal_to(416);
+        assert_that(&entry.reply.src).is_equal_to("1.2.3.9".parse::<IpAddr>().unwrap());
+        assert_that(&entry.reply.bytes).is_equal_to(0);
+        assert_that(&entry.accounting).is_false();
     }
 
     #[test]
     fn test_parse_conntrack_file() {
         let file = File::open("fixtures/nf_conntrack").unwrap();
-        let count = parse(file, 0, |count, entry| count + entry.bytes);
+        let count = parse(file, 0, |count, entry| {
+            count + entry.original.bytes + entry.reply.bytes
+        });
 
         assert_that(&count).is_ok_containing(192841);
     }
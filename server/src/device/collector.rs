@@ -1,41 +1,77 @@
 use super::parse;
-use crate::common::Trafic;
+use crate::common::source::{self, Reader, Source};
+use crate::common::{checkpoint, Poller, Trafic};
 use crate::config::Config;
-use actix::{Actor, AsyncContext, Context, Handler, Message};
+use crate::model::{CollectorHealth, RatesUpdate, Subscribe, WanCounters, WanTraffic, Window};
+use actix::prelude::SendError;
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, MessageResult};
 use chrono::{NaiveDateTime, Utc};
-use log::{debug, error};
-use std::fs::File;
+use log::{debug, error, info, warn};
 use std::io::{self, Read};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::Instant;
+
+const CHECKPOINT_FILE: &str = "wan.rrd";
 
 pub struct DeviceCollector {
     config: Config,
+    source: Arc<dyn Source>,
+    /// Started with the actor
+    reader: Option<Addr<Reader>>,
     traffic: Trafic,
+    subscribers: Vec<Subscribe>,
+    last_checkpoint: Instant,
+    poller: Poller,
 }
 
 #[derive(Message)]
 struct Ping;
 
+#[derive(Message)]
+#[rtype(result = "CollectorHealth")]
+pub struct Health;
+
+#[derive(Message)]
+#[rtype(result = "WanTraffic")]
+pub struct Snapshot(pub Option<Window>);
+
+#[derive(Message)]
+#[rtype(result = "WanCounters")]
+pub struct Counters;
+
 struct TrafficCollector<'a> {
     now: NaiveDateTime,
     interface: &'a str,
     traffic: &'a mut Trafic,
+    interfaces: usize,
+    found: bool,
 }
 
 impl<'a> TrafficCollector<'a> {
-    fn process<I: Read>(traffic: &mut Trafic, interface: &str, input: I) -> io::Result<()> {
+    /// Process a poll and get the number of interfaces
+    fn process<I: Read>(traffic: &mut Trafic, interface: &str, input: I) -> io::Result<usize> {
         let collector = TrafficCollector {
             now: Utc::now().naive_utc(),
             interface,
             traffic,
+            interfaces: 0,
+            found: false,
         };
-        parse::parse(input, collector, TrafficCollector::collect)?;
+        let collector = parse::parse(input, collector, TrafficCollector::collect)?;
 
-        Ok(())
+        if !collector.found {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Interface {} not found", interface),
+            ));
+        }
+        Ok(collector.interfaces)
     }
 
-    fn collect(self, stats: &parse::InterfaceStats) -> Self {
+    fn collect(mut self, stats: &parse::InterfaceStats) -> Self {
+        self.interfaces += 1;
         if stats.interface == self.interface {
+            self.found = true;
             self.traffic
                 .put_in(self.now, stats.receive_bytes, stats.receive_packets);
             self.traffic
@@ -47,16 +83,84 @@ impl<'a> TrafficCollector<'a> {
 
 impl DeviceCollector {
     pub fn new(config: Config) -> DeviceCollector {
+        let source = source::from_config(
+            &config.device_file,
+            &config.device_command,
+            config.poll.command_timeout,
+        );
+
+        Self::with_source(config, source)
+    }
+
+    pub fn with_source(config: Config, source: Box<dyn Source>) -> DeviceCollector {
         DeviceCollector {
-            traffic: Trafic::new(config.retain_data),
+            traffic: Self::restore(&config).unwrap_or_else(|| Trafic::new(&config.archives())),
+            poller: Poller::new(
+                "Process device file",
+                config.poll.device,
+                config.poll.max_backoff,
+            ),
             config,
+            source: source.into(),
+            reader: None,
+            subscribers: vec![],
+            last_checkpoint: Instant::now(),
+        }
+    }
+
+    fn restore(config: &Config) -> Option<Trafic> {
+        let path = config.state_dir.as_ref()?.join(CHECKPOINT_FILE);
+
+        let archives = config.archives();
+
+        match checkpoint::load(&path, |input| Trafic::read_from(input, &archives)) {
+            Ok(traffic) => {
+                if traffic.is_some() {
+                    info!("Restored WAN traffic from {}", path.display());
+                }
+                traffic
+            }
+            Err(error) => {
+                warn!("Ignoring checkpoint {}: {}", path.display(), error);
+                None
+            }
         }
     }
 
-    fn process_device_file(&mut self) -> io::Result<()> {
-        debug!("Collecting: {}", self.config.device_file);
-        let file = File::open(&self.config.device_file)?;
-        TrafficCollector::process(&mut self.traffic, &self.config.wan_interface, file)
+    fn checkpoint(&mut self) {
+        if let Some(state_dir) = &self.config.state_dir {
+            let path = state_dir.join(CHECKPOINT_FILE);
+            let traffic = &self.traffic;
+
+            debug!("Checkpoint: {}", path.display());
+            if let Err(error) = checkpoint::save(&path, |out| traffic.write_to(out)) {
+                error!("Checkpoint {} failed: {}", path.display(), error)
+            }
+        }
+        self.last_checkpoint = Instant::now();
+    }
+
+    fn process_device_file(&mut self, input: &[u8]) -> io::Result<usize> {
+        TrafficCollector::process(&mut self.traffic, &self.config.wan_interface, input)
+    }
+
+    fn publish(&mut self) {
+        if !self.subscribers.iter().any(Subscribe::is_active) {
+            return;
+        }
+        let update = RatesUpdate::Wan {
+            timestamp: Utc::now().naive_utc(),
+            interface: self.config.wan_interface.clone(),
+            in_rate: self.traffic.current_in_rate(),
+            out_rate: self.traffic.current_out_rate(),
+        };
+
+        self.subscribers.retain(|subscriber| {
+            !matches!(
+                subscriber.recipient.do_send(update.clone()),
+                Err(SendError::Closed(_))
+            )
+        });
     }
 }
 
@@ -64,10 +168,64 @@ impl Handler<Ping> for DeviceCollector {
     type Result = ();
 
     fn handle(&mut self, _: Ping, ctx: &mut Context<DeviceCollector>) {
-        if let Err(error) = self.process_device_file() {
-            error!("Process device file failed: {}", error)
-        }
-        ctx.notify_later(Ping, Duration::from_millis(500));
+        let reader = match &self.reader {
+            Some(reader) => reader,
+            None => return,
+        };
+        debug!("Collecting: {}", self.source.describe());
+        source::read_later(reader, ctx, |collector: &mut DeviceCollector, input, ctx| {
+            let result = input.and_then(|input| collector.process_device_file(&input));
+            let delay = collector.poller.record(result);
+            collector.publish();
+            if collector.last_checkpoint.elapsed() >= collector.config.checkpoint_interval {
+                collector.checkpoint();
+            }
+            ctx.notify_later(Ping, delay);
+        });
+    }
+}
+
+impl Handler<Health> for DeviceCollector {
+    type Result = MessageResult<Health>;
+
+    fn handle(&mut self, _: Health, _: &mut Context<DeviceCollector>) -> Self::Result {
+        MessageResult(self.poller.health())
+    }
+}
+
+impl Handler<Snapshot> for DeviceCollector {
+    type Result = MessageResult<Snapshot>;
+
+    fn handle(&mut self, msg: Snapshot, _: &mut Context<DeviceCollector>) -> Self::Result {
+        let snapshot = match msg.0 {
+            Some(window) => {
+                self.traffic
+                    .resample(window.from, window.to, window.step, window.aggregator)
+            }
+            None => self.traffic.snapshot(),
+        };
+
+        MessageResult(WanTraffic::new(self.config.wan_interface.clone(), snapshot))
+    }
+}
+
+impl Handler<Counters> for DeviceCollector {
+    type Result = MessageResult<Counters>;
+
+    fn handle(&mut self, _: Counters, _: &mut Context<DeviceCollector>) -> Self::Result {
+        MessageResult(WanCounters {
+            interface: self.config.wan_interface.clone(),
+            in_count: self.traffic.last_in(),
+            out_count: self.traffic.last_out(),
+        })
+    }
+}
+
+impl Handler<Subscribe> for DeviceCollector {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<DeviceCollector>) {
+        self.subscribers.push(msg);
     }
 }
 
@@ -75,6 +233,73 @@ impl Actor for DeviceCollector {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.reader = Some(Reader::start(self.source.clone()));
         ctx.notify(Ping);
     }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.checkpoint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::source::MemorySource;
+    use actix::{Actor, System, SystemRunner};
+    use spectral::prelude::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn config(wan_interface: &str) -> Config {
+        toml::from_str::<Config>(&format!(
+            r#"
+            local_subnets = ["192.168.3."]
+            wan_interface = "{}"
+        "#,
+            wan_interface
+        ))
+        .unwrap()
+    }
+
+    /// Reads are asynchronous, so wait until the first poll has been recorded
+    fn first_poll(sys: &mut SystemRunner, collector: &Addr<DeviceCollector>) -> CollectorHealth {
+        for _ in 0..100 {
+            let health = sys.block_on(collector.send(Health)).unwrap();
+            if health.last_success.is_some() || health.errors > 0 {
+                return health;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("The source has not been polled");
+    }
+
+    #[test]
+    fn test_scripted_device() {
+        let mut sys = System::new("test");
+        let source = MemorySource::new(vec![std::fs::read("fixtures/dev").unwrap()]);
+        let collector = DeviceCollector::with_source(config("enp2s0"), Box::new(source)).start();
+
+        let health = first_poll(&mut sys, &collector);
+        let counters = sys.block_on(collector.send(Counters)).unwrap();
+
+        assert_that(&health.last_success).is_some();
+        assert_that(&health.errors).is_equal_to(0);
+        assert_that(&counters.interface).is_equal_to("enp2s0".to_string());
+        assert_that(&counters.in_count.bytes).is_equal_to(475_948);
+        assert_that(&counters.out_count.bytes).is_equal_to(623_326);
+    }
+
+    #[test]
+    fn test_scripted_device_missing_interface() {
+        let mut sys = System::new("test");
+        let source = MemorySource::new(vec![std::fs::read("fixtures/dev").unwrap()]);
+        let collector = DeviceCollector::with_source(config("eth0"), Box::new(source)).start();
+
+        let health = first_poll(&mut sys, &collector);
+
+        assert_that(&health.last_success).is_none();
+        assert_that(&health.errors).is_equal_to(1);
+        assert_that(&health.last_error).is_some();
+    }
 }
@@ -1,4 +1,4 @@
 mod collector;
 mod parse;
 
-pub use collector::DeviceCollector;
+pub use collector::{Counters, DeviceCollector, Health, Snapshot};
@@ -0,0 +1,89 @@
+mod parse;
+
+use crate::common::source::{FileSource, Source};
+use crate::common::Subnet;
+use crate::config::Config;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Subnets per interface, without duplicates
+pub type InterfaceSubnets = BTreeMap<String, Vec<Subnet>>;
+
+/// Current subnets of the local interfaces, e.g. to follow a rotating IPv6 prefix
+pub struct Discovery {
+    inet6: Box<dyn Source>,
+    routes: Box<dyn Source>,
+    subnets: InterfaceSubnets,
+}
+
+fn collect(mut subnets: InterfaceSubnets, entry: &parse::InterfaceSubnet<'_>) -> InterfaceSubnets {
+    let interface = subnets.entry(entry.interface.to_string()).or_default();
+
+    if !interface.contains(&entry.subnet) {
+        interface.push(entry.subnet);
+    }
+    subnets
+}
+
+impl Discovery {
+    pub fn new(config: &Config) -> Discovery {
+        Self::with_sources(
+            Box::new(FileSource(PathBuf::from(&config.interfaces_inet6_file))),
+            Box::new(FileSource(PathBuf::from(&config.interfaces_route_file))),
+        )
+    }
+
+    pub fn with_sources(inet6: Box<dyn Source>, routes: Box<dyn Source>) -> Discovery {
+        Discovery {
+            inet6,
+            routes,
+            subnets: Default::default(),
+        }
+    }
+
+    pub fn subnets(&self) -> &InterfaceSubnets {
+        &self.subnets
+    }
+
+    /// Read the interface addresses again, `true` if they have changed
+    pub fn refresh(&mut self) -> io::Result<bool> {
+        let subnets = parse::parse_routes(self.routes.read()?, Default::default(), collect)?;
+        let subnets = parse::parse_inet6(self.inet6.read()?, subnets, collect)?;
+
+        if subnets == self.subnets {
+            return Ok(false);
+        }
+        self.subnets = subnets;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::source::MemorySource;
+    use spectral::prelude::*;
+
+    #[test]
+    fn test_refresh() {
+        let route =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n";
+        let mut discovery = Discovery::with_sources(
+            Box::new(MemorySource::new(vec![
+                "20010db8abcd00100000000000000001 03 40 00 80   br-lan\n",
+                "20010db8abcd00100000000000000001 03 40 00 80   br-lan\n",
+                "20010db8abcd00200000000000000001 03 40 00 80   br-lan\n",
+            ])),
+            Box::new(MemorySource::new(vec![route])),
+        );
+
+        assert_that(&discovery.refresh().unwrap()).is_true();
+        assert_that(&discovery.subnets()["br-lan"])
+            .is_equal_to(vec!["2001:db8:abcd:10::/64".parse().unwrap()]);
+        assert_that(&discovery.refresh().unwrap()).is_false();
+        assert_that(&discovery.refresh().unwrap()).is_true();
+        assert_that(&discovery.subnets()["br-lan"])
+            .is_equal_to(vec!["2001:db8:abcd:20::/64".parse().unwrap()]);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::common::Subnet;
+use log::debug;
+use nom::bytes::complete::{take_till1, take_while_m_n};
+use nom::character::complete::{digit1, hex_digit1, space0, space1};
+use nom::combinator::map_res;
+use nom::error::{ParseError, VerboseError};
+use nom::multi::count;
+use nom::sequence::preceded;
+use nom::IResult;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Scope of globally routed addresses in /proc/net/if_inet6, link-local is 0x20
+const SCOPE_GLOBAL: u8 = 0;
+const RTF_UP: u16 = 0x0001;
+const RTF_GATEWAY: u16 = 0x0002;
+
+#[derive(Debug, PartialEq)]
+pub struct InterfaceSubnet<'a> {
+    pub interface: &'a str,
+    pub subnet: Subnet,
+}
+
+fn hex<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, u32, E> {
+    map_res(hex_digit1, |hex| u32::from_str_radix(hex, 16))(input)
+}
+
+fn interface<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
+    take_till1(|ch: char| ch.is_whitespace())(input)
+}
+
+/// Line of `/proc/net/if_inet6`: address, index, prefix length, scope, flags and interface
+fn parse_inet6_line<'a, E: ParseError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, (InterfaceSubnet<'a>, u8), E> {
+    let (input, addr) = map_res(
+        take_while_m_n(32, 32, |ch: char| ch.is_ascii_hexdigit()),
+        |hex| u128::from_str_radix(hex, 16),
+    )(input)?;
+    let (input, _) = preceded(space1, hex_digit1)(input)?;
+    let (input, prefix_len) = preceded(space1, hex)(input)?;
+    let (input, scope) = preceded(space1, hex)(input)?;
+    let (input, _) = preceded(space1, hex_digit1)(input)?;
+    let (input, interface) = preceded(space1, interface)(input)?;
+    let (input, _) = space0(input)?;
+    let subnet = Subnet::new(Ipv6Addr::from(addr).into(), prefix_len as u8)
+        .ok_or_else(|| nom::Err::Error(E::from_error_kind(input, nom::error::ErrorKind::Verify)))?;
+
+    Ok((input, (InterfaceSubnet { interface, subnet }, scope as u8)))
+}
+
+/// Line of `/proc/net/route`, addresses are printed in host byte order
+fn parse_route_line<'a, E: ParseError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, (InterfaceSubnet<'a>, u16, u32), E> {
+    let (input, interface) = interface(input)?;
+    let (input, destination) = preceded(space1, hex)(input)?;
+    let (input, gateway) = preceded(space1, hex)(input)?;
+    let (input, flags) = preceded(space1, hex)(input)?;
+    let (input, _) = count(preceded(space1, digit1), 3)(input)?;
+    let (input, mask) = preceded(space1, hex)(input)?;
+    let (input, _) = count(preceded(space1, digit1), 3)(input)?;
+    let (input, _) = space0(input)?;
+    let prefix_len = u32::from_be_bytes(mask.to_ne_bytes()).count_ones() as u8;
+    let subnet = Subnet::new(Ipv4Addr::from(destination.to_ne_bytes()).into(), prefix_len)
+        .ok_or_else(|| nom::Err::Error(E::from_error_kind(input, nom::error::ErrorKind::Verify)))?;
+
+    Ok((
+        input,
+        (InterfaceSubnet { interface, subnet }, flags as u16, gateway),
+    ))
+}
+
+/// Global IPv6 subnets, e.g. the delegated prefix of a LAN interface
+pub fn parse_inet6<I, V, C>(input: I, mut initial: C, visitor: V) -> io::Result<C>
+where
+    I: Read,
+    V: Fn(C, &InterfaceSubnet<'_>) -> C,
+{
+    let buf_reader = BufReader::new(input);
+
+    for line_result in buf_reader.lines() {
+        let line = line_result?;
+
+        match parse_inet6_line::<VerboseError<&str>>(&line) {
+            Ok((_, (subnet, SCOPE_GLOBAL))) => initial = visitor(initial, &subnet),
+            Ok(_) => (),
+            Err(error) => {
+                debug!("Invalid if_inet6 entry: {:?}", error);
+            }
+        }
+    }
+    Ok(initial)
+}
+
+/// IPv4 subnets directly connected to an interface (routes without gateway)
+pub fn parse_routes<I, V, C>(input: I, mut initial: C, visitor: V) -> io::Result<C>
+where
+    I: Read,
+    V: Fn(C, &InterfaceSubnet<'_>) -> C,
+{
+    let buf_reader = BufReader::new(input);
+
+    for (i, line_result) in buf_reader.lines().enumerate() {
+        if i < 1 {
+            continue;
+        }
+        let line = line_result?;
+
+        match parse_route_line::<VerboseError<&str>>(&line) {
+            Ok((_, (subnet, flags, 0)))
+                if flags & (RTF_UP | RTF_GATEWAY) == RTF_UP
+                    && subnet.subnet != Subnet::V4(Ipv4Addr::UNSPECIFIED, 0) =>
+            {
+                initial = visitor(initial, &subnet)
+            }
+            Ok(_) => (),
+            Err(error) => {
+                debug!("Invalid route entry: {:?}", error);
+            }
+        }
+    }
+    Ok(initial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::error::VerboseError;
+    use spectral::prelude::*;
+
+    fn collect(
+        subnets: Vec<(String, Subnet)>,
+        subnet: &InterfaceSubnet<'_>,
+    ) -> Vec<(String, Subnet)> {
+        let mut subnets = subnets;
+        subnets.push((subnet.interface.to_string(), subnet.subnet));
+        subnets
+    }
+
+    #[test]
+    fn test_parse_inet6_line() {
+        let input = "20010db8abcd00100000000000000001 03 40 00 80   br-lan";
+        let (remain, (subnet, scope)) = parse_inet6_line::<VerboseError<&str>>(input).unwrap();
+
+        assert_that(&remain).is_equal_to("");
+        assert_that(&subnet.interface).is_equal_to("br-lan");
+        assert_that(&subnet.subnet).is_equal_to("2001:db8:abcd:10::/64".parse::<Subnet>().unwrap());
+        assert_that(&scope).is_equal_to(SCOPE_GLOBAL);
+    }
+
+    #[test]
+    fn test_parse_inet6() {
+        let input = r#"00000000000000000000000000000001 01 80 10 80       lo
+fe80000000000000021122fffe334455 03 40 20 80   br-lan
+20010db8abcd00100000000000000001 03 40 00 80   br-lan
+20010db8abcd0010a1b2c3d4e5f60718 03 40 00 01   br-lan
+20010db8ffff00000000000000000002 02 80 00 80     eth0
+"#;
+        let subnets = parse_inet6(input.as_bytes(), vec![], collect).unwrap();
+
+        assert_that(&subnets).is_equal_to(vec![
+            (
+                "br-lan".to_string(),
+                "2001:db8:abcd:10::/64".parse().unwrap(),
+            ),
+            (
+                "br-lan".to_string(),
+                "2001:db8:abcd:10::/64".parse().unwrap(),
+            ),
+            ("eth0".to_string(), "2001:db8:ffff::2/128".parse().unwrap()),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_routes() {
+        let bytes = |addr: [u8; 4]| format!("{:08X}", u32::from_ne_bytes(addr));
+        let input = format!(
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+             eth0\t{}\t{}\t0003\t0\t0\t0\t{}\t0\t0\t0\n\
+             eth0\t{}\t{}\t0001\t0\t0\t0\t{}\t0\t0\t0\n\
+             br-lan\t{}\t{}\t0001\t0\t0\t0\t{}\t0\t0\t0\n\
+             br-lan\t{}\t{}\t0000\t0\t0\t0\t{}\t0\t0\t0\n",
+            bytes([0, 0, 0, 0]),
+            bytes([100, 64, 0, 1]),
+            bytes([0, 0, 0, 0]),
+            bytes([100, 64, 0, 0]),
+            bytes([0, 0, 0, 0]),
+            bytes([255, 255, 192, 0]),
+            bytes([192, 168, 3, 0]),
+            bytes([0, 0, 0, 0]),
+            bytes([255, 255, 255, 0]),
+            bytes([192, 168, 4, 0]),
+            bytes([0, 0, 0, 0]),
+            bytes([255, 255, 255, 0]),
+        );
+        let subnets = parse_routes(input.as_bytes(), vec![], collect).unwrap();
+
+        assert_that(&subnets).is_equal_to(vec![
+            ("eth0".to_string(), "100.64.0.0/18".parse().unwrap()),
+            ("br-lan".to_string(), "192.168.3.0/24".parse().unwrap()),
+        ]);
+    }
+}
@@ -1,43 +1,65 @@
 use super::parse;
 use super::Lease;
+use crate::common::source::{self, Reader, Source};
+use crate::common::Poller;
 use crate::config::Config;
-use actix::{Actor, AsyncContext, Context, Handler, Message, MessageResult};
-use log::{debug, error};
+use crate::model::CollectorHealth;
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, MessageResult};
+use log::debug;
 use std::collections::HashMap;
-use std::fs::File;
 use std::io;
 use std::net::IpAddr;
-use std::time::Duration;
+use std::sync::Arc;
 
 pub struct LeasesCollector {
-    config: Config,
+    source: Arc<dyn Source>,
+    /// Started with the actor
+    reader: Option<Addr<Reader>>,
     leases: Vec<Lease>,
+    poller: Poller,
 }
 
 #[derive(Message)]
 struct Ping;
 
+#[derive(Message)]
+#[rtype(result = "CollectorHealth")]
+pub struct Health;
+
 #[derive(Message)]
 #[rtype(result = "HashMap<IpAddr, Lease>")]
-struct Snapshot;
+pub struct Snapshot;
 
 impl LeasesCollector {
     pub fn new(config: Config) -> LeasesCollector {
+        let source = source::from_config(
+            &config.leases_file,
+            &config.leases_command,
+            config.poll.command_timeout,
+        );
+
+        Self::with_source(config, source)
+    }
+
+    pub fn with_source(config: Config, source: Box<dyn Source>) -> LeasesCollector {
         LeasesCollector {
-            config,
+            source: source.into(),
+            reader: None,
             leases: Default::default(),
+            poller: Poller::new(
+                "Process leases file",
+                config.poll.leases,
+                config.poll.max_backoff,
+            ),
         }
     }
 
-    fn process_leases_file(&mut self) -> io::Result<()> {
-        debug!("Collecting: {}", self.config.leases_file);
-        let file = File::open(&self.config.leases_file)?;
-
-        self.leases = parse::parse(file, vec![], |mut leases, lease| {
+    fn process_leases_file(&mut self, input: &[u8]) -> io::Result<usize> {
+        self.leases = parse::parse(input, vec![], |mut leases, lease| {
             leases.push(lease);
             leases
         })?;
-        Ok(())
+        Ok(self.leases.len())
     }
 }
 
@@ -45,10 +67,24 @@ impl Handler<Ping> for LeasesCollector {
     type Result = ();
 
     fn handle(&mut self, _: Ping, ctx: &mut Context<LeasesCollector>) {
-        if let Err(error) = self.process_leases_file() {
-            error!("Process device file failed: {}", error)
-        }
-        ctx.notify_later(Ping, Duration::from_millis(500));
+        let reader = match &self.reader {
+            Some(reader) => reader,
+            None => return,
+        };
+        debug!("Collecting: {}", self.source.describe());
+        source::read_later(reader, ctx, |collector: &mut LeasesCollector, input, ctx| {
+            let result = input.and_then(|input| collector.process_leases_file(&input));
+            let delay = collector.poller.record(result);
+            ctx.notify_later(Ping, delay);
+        });
+    }
+}
+
+impl Handler<Health> for LeasesCollector {
+    type Result = MessageResult<Health>;
+
+    fn handle(&mut self, _: Health, _: &mut Context<LeasesCollector>) -> Self::Result {
+        MessageResult(self.poller.health())
     }
 }
 
@@ -69,6 +105,53 @@ impl Actor for LeasesCollector {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.reader = Some(Reader::start(self.source.clone()));
         ctx.notify(Ping);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::source::MemorySource;
+    use actix::{System, SystemRunner};
+    use spectral::prelude::*;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Reads are asynchronous, so wait until the first poll has been recorded
+    fn first_poll(sys: &mut SystemRunner, collector: &Addr<LeasesCollector>) -> CollectorHealth {
+        for _ in 0..100 {
+            let health = sys.block_on(collector.send(Health)).unwrap();
+            if health.last_success.is_some() || health.errors > 0 {
+                return health;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("The source has not been polled");
+    }
+
+    #[test]
+    fn test_scripted_leases() {
+        let mut sys = System::new("test");
+        let source = MemorySource::new(vec![
+            "1562559403 9c:b6:d0:12:34:56 192.168.3.144 Hyperion 01:9c:b6:d0:12:34:56\n",
+        ]);
+        let config = toml::from_str::<Config>(
+            r#"
+            local_subnets = ["192.168.3."]
+            wan_interface = "eth0"
+        "#,
+        )
+        .unwrap();
+        let collector = LeasesCollector::with_source(config, Box::new(source)).start();
+
+        let health = first_poll(&mut sys, &collector);
+        let leases = sys.block_on(collector.send(Snapshot)).unwrap();
+
+        assert_that(&health.errors).is_equal_to(0);
+        assert_that(&leases.len()).is_equal_to(1);
+        assert_that(&leases[&"192.168.3.144".parse().unwrap()].name)
+            .is_equal_to("Hyperion".to_string());
+    }
+}
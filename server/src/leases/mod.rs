@@ -2,5 +2,5 @@ mod collector;
 mod model;
 mod parse;
 
-pub use collector::LeasesCollector;
+pub use collector::{Health, LeasesCollector, Snapshot};
 pub use model::*;
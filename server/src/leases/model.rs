@@ -1,8 +1,43 @@
+use serde_derive::Serialize;
 use std::net::IpAddr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Lease {
     pub name: String,
     pub addr: IpAddr,
+    pub mac: Option<String>,
     pub client_id: String,
 }
+
+impl Lease {
+    pub fn hostname(&self) -> Option<&str> {
+        if self.name == "*" {
+            None
+        } else {
+            Some(&self.name)
+        }
+    }
+
+    pub fn hardware_addr(&self) -> Option<String> {
+        if let Some(mac) = &self.mac {
+            return Some(mac.to_lowercase());
+        }
+        let client_id = self.client_id.to_lowercase();
+        let octets = client_id.split(':').collect::<Vec<&str>>();
+
+        match octets.as_slice() {
+            // DHCPv4 client identifier with ethernet hardware type
+            ["01", mac @ ..] if mac.len() == 6 => Some(mac.join(":")),
+            // DUID-LLT with ethernet hardware type
+            ["00", "01", "00", "01", _, _, _, _, mac @ ..] if mac.len() == 6 => Some(mac.join(":")),
+            // DUID-LL with ethernet hardware type
+            ["00", "03", "00", "01", mac @ ..] if mac.len() == 6 => Some(mac.join(":")),
+            _ => None,
+        }
+    }
+
+    pub fn device_key(&self) -> String {
+        self.hardware_addr()
+            .unwrap_or_else(|| self.client_id.to_lowercase())
+    }
+}
@@ -27,7 +27,7 @@ fn mac_like<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a
 
 fn parse_line<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Lease, E> {
     let (input, _) = digit1(input)?;
-    let (input, _) = preceded(space1, mac_like)(input)?;
+    let (input, hwaddr) = preceded(space1, mac_like)(input)?;
     let (input, addr) = preceded(space1, ip_addr)(input)?;
     let (input, name) = preceded(space1, hostname)(input)?;
     let (input, client_id) = preceded(space1, mac_like)(input)?;
@@ -37,6 +37,11 @@ fn parse_line<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Le
         Lease {
             name: name.to_string(),
             addr,
+            mac: if hwaddr.contains(':') {
+                Some(hwaddr.to_string())
+            } else {
+                None
+            },
             client_id: client_id.to_string(),
         },
     ))
@@ -77,6 +82,7 @@ mod tests {
         assert_that(&remain).is_equal_to("");
         assert_that(&lease.name).is_equal_to("brick".to_string());
         assert_that(&lease.addr).is_equal_to("192.168.3.86".parse::<IpAddr>().unwrap());
+        assert_that(&lease.mac).contains_value("24:5e:be:12:34:56".to_string());
         assert_that(&lease.client_id).is_equal_to("01:24:5e:be:12:34:56".to_string());
     }
 
@@ -88,6 +94,7 @@ mod tests {
         assert_that(&remain).is_equal_to("");
         assert_that(&lease.name).is_equal_to("thunder".to_string());
         assert_that(&lease.addr).is_equal_to("1234::28a".parse::<IpAddr>().unwrap());
+        assert_that(&lease.mac).is_none();
         assert_that(&lease.client_id)
             .is_equal_to("00:04:2e:3b:43:05:a5:df:ad:a0:32:bb:a8:a8:d3:12:34:56".to_string());
     }
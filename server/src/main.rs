@@ -1,30 +1,36 @@
 use actix::{Actor, Addr, System};
-use actix_web::{web, App, HttpServer, Responder};
+use actix_web::{web, App, HttpServer};
 
+mod api;
+mod auth;
 mod cli;
 mod common;
 mod config;
 mod conntrack;
 mod device;
+mod interfaces;
 mod leases;
 mod minirrd;
-mod minivec;
 mod model;
+mod tls;
+mod ui;
 
-use config::Config;
+use api::Broadcaster;
+use auth::Auth;
+use config::{BindAddr, Config};
 use conntrack::ConntrackCollector;
 use device::DeviceCollector;
 use leases::LeasesCollector;
+use model::Subscribe;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
 
 #[derive(Clone)]
 struct Container {
     conntrack: Addr<ConntrackCollector>,
     device: Addr<DeviceCollector>,
     leases: Addr<LeasesCollector>,
-}
-
-fn index(info: web::Path<(u32, String)>) -> impl Responder {
-    format!("Hello {}! id:{}", info.1, info.0)
+    broadcaster: Addr<Broadcaster>,
 }
 
 fn main() -> std::io::Result<()> {
@@ -43,19 +49,66 @@ fn main() -> std::io::Result<()> {
 
     let sys = System::new("nftracker");
 
+    let broadcaster = Broadcaster::default();
+    let active = broadcaster.active();
+    let broadcaster = broadcaster.start();
+    let conntrack = ConntrackCollector::new(config.clone()).start();
+    let device = DeviceCollector::new(config.clone()).start();
+
+    conntrack.do_send(Subscribe {
+        recipient: broadcaster.clone().recipient(),
+        active: active.clone(),
+    });
+    device.do_send(Subscribe {
+        recipient: broadcaster.clone().recipient(),
+        active,
+    });
+
+    let http_config = config.http.clone();
+    let tls_config = match &http_config.tls {
+        Some(tls) => Some(tls::server_config(tls)?),
+        None => None,
+    };
+    let auth_config = config.auth.clone();
+    let metrics_config = web::Data::new(config.metrics.clone());
+    let health_config = web::Data::new(config.health.clone());
+    let archives = web::Data::new(config.archives());
     let container = web::Data::new(Container {
-        conntrack: ConntrackCollector::new(config.clone()).start(),
-        device: DeviceCollector::new(config.clone()).start(),
+        conntrack,
+        device,
         leases: LeasesCollector::new(config).start(),
+        broadcaster,
     });
 
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
+            .wrap(Auth::new(auth_config.clone()))
             .register_data(container.clone())
-            .service(web::resource("/{id}/{name}/index.html").to(index))
-    })
-    .bind("0.0.0.0:8080")?
-    .start();
+            .register_data(metrics_config.clone())
+            .register_data(health_config.clone())
+            .register_data(archives.clone())
+            .configure(api::configure)
+            .configure(ui::configure)
+    });
+
+    for bind in &http_config.bind {
+        server = match (bind, &tls_config) {
+            (BindAddr::Tcp(addr), Some(tls_config)) => {
+                server.bind_rustls(addr, tls_config.clone())?
+            }
+            (BindAddr::Tcp(addr), None) => server.bind(addr)?,
+            (BindAddr::Unix(path), _) => {
+                // Remove a stale socket of a previous run, but never anything else
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        fs::remove_file(path)?;
+                    }
+                }
+                server.bind_uds(path)?
+            }
+        };
+    }
+    server.start();
 
     sys.run()
 }
@@ -0,0 +1,69 @@
+use super::{RRDEntry, RRD};
+use chrono::NaiveDateTime;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Archive {
+    #[serde(with = "humantime_serde")]
+    pub resolution: Duration,
+    #[serde(with = "humantime_serde")]
+    pub retain: Duration,
+}
+
+impl Archive {
+    pub(super) fn resolution_millis(&self) -> usize {
+        self.resolution.as_millis() as usize
+    }
+
+    /// Number of slots of the RRD holding this archive
+    pub(super) fn capacity(&self) -> usize {
+        self.retain.as_millis() as usize / self.resolution_millis()
+    }
+}
+
+/// Set of RRDs at increasing resolutions. Every entry goes into all of them, the coarser
+/// archives consolidate all entries falling into one of their slots.
+#[derive(Debug, Clone)]
+pub struct MultiRRD<E> {
+    pub(super) archives: Vec<RRD<E>>,
+}
+
+impl<E: RRDEntry> MultiRRD<E> {
+    pub fn new(start: NaiveDateTime, archives: &[Archive]) -> Self {
+        assert!(!archives.is_empty());
+
+        let mut archives = archives
+            .iter()
+            .map(|archive| RRD::new(start, archive.resolution, archive.retain))
+            .collect::<Vec<_>>();
+        archives.sort_by_key(RRD::resolution);
+
+        MultiRRD { archives }
+    }
+
+    pub fn put(&mut self, timestamp: NaiveDateTime, entry: E) -> bool {
+        let (finest, coarser) = self.archives.split_first_mut().unwrap();
+
+        if !finest.put(timestamp, entry.clone()) {
+            return false;
+        }
+        for archive in coarser {
+            archive.consolidate(timestamp, entry.clone());
+        }
+        true
+    }
+
+    pub fn finest(&self) -> &RRD<E> {
+        &self.archives[0]
+    }
+
+    /// The finest archive that still has all data since `from`. If no archive reaches back
+    /// that far the coarsest one is the best we can do.
+    pub fn covering(&self, from: NaiveDateTime) -> &RRD<E> {
+        self.archives
+            .iter()
+            .find(|archive| archive.first_timestamp() <= from || archive.len() < archive.capacity())
+            .unwrap_or_else(|| &self.archives[self.archives.len() - 1])
+    }
+}
@@ -2,15 +2,45 @@ use chrono::NaiveDateTime;
 use std::fmt::Debug;
 use std::time::Duration;
 
+mod archive;
+mod persist;
 #[cfg(test)]
 mod tests;
 
+pub use archive::*;
+pub use persist::*;
+
+/// Upper bound of the buckets `RRD::resample` produces
+pub const MAX_BUCKETS: usize = 10_000;
+
 pub trait RRDEntry: Default + Debug + Clone {
     fn combine(self, other: &Self) -> Self;
 
     fn interpolate(&self, previous: &Self, index: u64, steps: u64) -> Self;
+
+    /// Merge a newer entry into a slot of a coarser archive that already holds `older`
+    fn consolidate(self, older: &Self) -> Self;
 }
 
+/// Entries that can be downsampled by `RRD::resample`
+pub trait Aggregatable: RRDEntry {
+    fn max(&self, other: &Self) -> Self;
+
+    fn sum(&self, other: &Self) -> Self;
+
+    fn divide(&self, count: u64) -> Self;
+
+    fn delta(&self, previous: &Self) -> Self;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregator {
+    Max,
+    Mean,
+    DeltaSum,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub struct RRD<E> {
     resolution: chrono::Duration,
@@ -56,6 +86,14 @@ impl<E: RRDEntry> RRD<E> {
         }
     }
 
+    pub fn resolution(&self) -> chrono::Duration {
+        self.resolution
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.len()
+    }
+
     pub fn first_timestamp(&self) -> NaiveDateTime {
         self.first_timestamp
     }
@@ -64,6 +102,10 @@ impl<E: RRDEntry> RRD<E> {
         self.last_timestamp
     }
 
+    pub fn last(&self) -> (NaiveDateTime, &E) {
+        (self.last_timestamp, &self.ring[self.last_index])
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (NaiveDateTime, &E)> {
         RRDIterator {
             rrd: self,
@@ -73,6 +115,17 @@ impl<E: RRDEntry> RRD<E> {
         }
     }
 
+    /// All entries with `from <= timestamp <= to`
+    pub fn range(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> impl Iterator<Item = (NaiveDateTime, &E)> {
+        self.iter()
+            .skip_while(move |(timestamp, _)| *timestamp < from)
+            .take_while(move |(timestamp, _)| *timestamp <= to)
+    }
+
     pub fn get(&self, offset: usize) -> Option<(NaiveDateTime, &E)> {
         if offset >= self.len() {
             return None;
@@ -88,6 +141,14 @@ impl<E: RRDEntry> RRD<E> {
     }
 
     pub fn put(&mut self, timestamp: NaiveDateTime, entry: E) -> bool {
+        self.merge(timestamp, entry, E::combine)
+    }
+
+    pub fn consolidate(&mut self, timestamp: NaiveDateTime, entry: E) -> bool {
+        self.merge(timestamp, entry, E::consolidate)
+    }
+
+    fn merge(&mut self, timestamp: NaiveDateTime, entry: E, merge: fn(E, &E) -> E) -> bool {
         if timestamp < self.first_timestamp {
             return false;
         }
@@ -126,12 +187,80 @@ impl<E: RRDEntry> RRD<E> {
             if index < 0 {
                 index += self.ring.len() as i64;
             }
-            let combined = entry.combine(&self.ring[index as usize]);
+            let combined = merge(entry, &self.ring[index as usize]);
             self.ring[index as usize] = combined;
         }
         true
     }
 
+    /// Downsample `from..=to` to buckets of `step` starting at `from`. Buckets without any
+    /// entry are `Default`. For `DeltaSum` the first entry of a bucket is compared to the
+    /// one before it, even if that is before `from`. Empty if `step` is below a millisecond
+    /// or there would be more than `MAX_BUCKETS` buckets.
+    pub fn resample(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        step: chrono::Duration,
+        aggregator: Aggregator,
+    ) -> Vec<(NaiveDateTime, E)>
+    where
+        E: Aggregatable,
+    {
+        // Buckets are indexed by milliseconds, so a fraction would shift them
+        let step_millis = step.num_milliseconds();
+        let step = chrono::Duration::milliseconds(step_millis);
+
+        if step_millis <= 0
+            || to < from
+            || (to - from).num_milliseconds() / step_millis >= MAX_BUCKETS as i64
+        {
+            return vec![];
+        }
+
+        let mut buckets = vec![];
+        let mut bucket_start = from;
+
+        while bucket_start <= to {
+            buckets.push((bucket_start, None::<E>, 0u64));
+            bucket_start += step;
+        }
+
+        let mut previous: Option<&E> = None;
+        for (timestamp, entry) in self.iter().take_while(|(timestamp, _)| *timestamp <= to) {
+            if timestamp >= from {
+                let index = ((timestamp - from).num_milliseconds() / step_millis) as usize;
+                let (_, value, count) = &mut buckets[index];
+                let sample = match aggregator {
+                    Aggregator::DeltaSum => previous.map(|previous| entry.delta(previous)),
+                    _ => Some(entry.clone()),
+                };
+
+                if let Some(sample) = sample {
+                    *value = Some(match (value.take(), aggregator) {
+                        (None, _) => sample,
+                        (Some(value), Aggregator::Max) => value.max(&sample),
+                        (Some(value), _) => value.sum(&sample),
+                    });
+                    *count += 1;
+                }
+            }
+            previous = Some(entry);
+        }
+
+        buckets
+            .into_iter()
+            .map(|(bucket_start, value, count)| {
+                let value = match (value, aggregator) {
+                    (Some(value), Aggregator::Mean) => value.divide(count),
+                    (Some(value), _) => value,
+                    (None, _) => E::default(),
+                };
+                (bucket_start, value)
+            })
+            .collect()
+    }
+
     fn advance_last(&mut self) {
         self.last_index += 1;
         self.last_timestamp += self.resolution;
@@ -0,0 +1,169 @@
+use super::{Archive, MultiRRD, RRDEntry, RRD};
+use chrono::NaiveDateTime;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Compact binary (little endian) encoding used to checkpoint RRDs to disk
+pub trait Persist: Sized {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()>;
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self>;
+}
+
+impl Persist for u8 {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&[*self])
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut buf = [0u8; 1];
+        input.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+}
+
+impl Persist for u16 {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut buf = [0u8; 2];
+        input.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+}
+
+impl Persist for u64 {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut buf = [0u8; 8];
+        input.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+impl Persist for i64 {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut buf = [0u8; 8];
+        input.read_exact(&mut buf)?;
+        Ok(i64::from_le_bytes(buf))
+    }
+}
+
+impl Persist for NaiveDateTime {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.timestamp_millis().write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let millis = i64::read_from(input)?;
+
+        NaiveDateTime::from_timestamp_opt(
+            millis.div_euclid(1_000),
+            millis.rem_euclid(1_000) as u32 * 1_000_000,
+        )
+        .ok_or_else(|| invalid_data("timestamp out of range"))
+    }
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Header: resolution, first/last timestamp, first/last index, ring length. Then the ring.
+impl<E: RRDEntry + Persist> RRD<E> {
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (self.resolution_millis as u64).write_to(out)?;
+        self.first_timestamp.write_to(out)?;
+        self.last_timestamp.write_to(out)?;
+        (self.first_index as u64).write_to(out)?;
+        (self.last_index as u64).write_to(out)?;
+        (self.ring.len() as u64).write_to(out)?;
+        for entry in &self.ring {
+            entry.write_to(out)?;
+        }
+        Ok(())
+    }
+
+    /// Read an RRD that is expected to hold `archive`. The layout is checked before anything
+    /// is allocated, so a corrupt header cannot exhaust the memory.
+    pub fn read_from<R: Read>(input: &mut R, archive: &Archive) -> io::Result<Self> {
+        let resolution_millis = u64::read_from(input)? as usize;
+        let first_timestamp = NaiveDateTime::read_from(input)?;
+        let last_timestamp = NaiveDateTime::read_from(input)?;
+        let first_index = u64::read_from(input)? as usize;
+        let last_index = u64::read_from(input)? as usize;
+        let len = u64::read_from(input)? as usize;
+
+        if resolution_millis != archive.resolution_millis() || len != archive.capacity() {
+            return Err(invalid_data("archive configuration has changed"));
+        }
+        if len == 0 || first_index >= len || last_index >= len || last_timestamp < first_timestamp {
+            return Err(invalid_data("inconsistent RRD header"));
+        }
+        // The timestamps have to match the slots between the indices, `merge` relies on that
+        let slots = (last_index + len - first_index) % len;
+        let aligned =
+            |timestamp: NaiveDateTime| timestamp.timestamp_millis() % resolution_millis as i64 == 0;
+        if (last_timestamp - first_timestamp).num_milliseconds()
+            != (slots * resolution_millis) as i64
+            || !aligned(first_timestamp)
+            || !aligned(last_timestamp)
+        {
+            return Err(invalid_data("inconsistent RRD header"));
+        }
+        let mut ring = Vec::with_capacity(len);
+        for _ in 0..len {
+            ring.push(E::read_from(input)?);
+        }
+
+        Ok(RRD {
+            resolution: chrono::Duration::milliseconds(resolution_millis as i64),
+            resolution_millis,
+            first_timestamp,
+            last_timestamp,
+            first_index,
+            last_index,
+            ring,
+        })
+    }
+}
+
+impl<E: RRDEntry + Persist> MultiRRD<E> {
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (self.archives.len() as u8).write_to(out)?;
+        for archive in &self.archives {
+            archive.write_to(out)?;
+        }
+        Ok(())
+    }
+
+    /// Read the archives of a (possibly changed) configuration, a different layout is
+    /// rejected as invalid data
+    pub fn read_from<R: Read>(input: &mut R, archives: &[Archive]) -> io::Result<Self> {
+        let count = u8::read_from(input)? as usize;
+
+        if count == 0 {
+            return Err(invalid_data("no archives"));
+        }
+        if count != archives.len() {
+            return Err(invalid_data("archive configuration has changed"));
+        }
+        let mut archives = archives.to_vec();
+        archives.sort_by_key(|archive| archive.resolution);
+
+        Ok(MultiRRD {
+            archives: archives
+                .iter()
+                .map(|archive| RRD::read_from(input, archive))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+}
@@ -1,6 +1,8 @@
 use super::*;
 use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use spectral::prelude::*;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
 use std::time::Duration;
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Copy)]
@@ -14,6 +16,38 @@ impl RRDEntry for Counter {
     fn interpolate(&self, previous: &Self, index: u64, steps: u64) -> Self {
         Counter(previous.0 + (self.0 - previous.0) * index / steps)
     }
+
+    fn consolidate(self, _older: &Self) -> Self {
+        self
+    }
+}
+
+impl Aggregatable for Counter {
+    fn max(&self, other: &Self) -> Self {
+        Counter(self.0.max(other.0))
+    }
+
+    fn sum(&self, other: &Self) -> Self {
+        Counter(self.0 + other.0)
+    }
+
+    fn divide(&self, count: u64) -> Self {
+        Counter(self.0 / count)
+    }
+
+    fn delta(&self, previous: &Self) -> Self {
+        Counter(self.0 - previous.0)
+    }
+}
+
+impl Persist for Counter {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.0.write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        Ok(Counter(u64::read_from(input)?))
+    }
 }
 
 #[test]
@@ -147,3 +181,190 @@ fn test_interpolate_gaps() {
         panic!("No point at {}", 599)
     }
 }
+
+#[test]
+fn test_multi_archives() {
+    let start = NaiveDateTime::new(
+        NaiveDate::from_ymd(2000, 1, 1),
+        NaiveTime::from_hms(0, 0, 0),
+    );
+    let mut rrd = MultiRRD::<Counter>::new(
+        start,
+        &[
+            Archive {
+                resolution: Duration::from_secs(10),
+                retain: Duration::from_secs(60),
+            },
+            Archive {
+                resolution: Duration::from_secs(1),
+                retain: Duration::from_secs(10),
+            },
+        ],
+    );
+
+    for i in 0..30 {
+        assert_that(&rrd.put(start + chrono::Duration::seconds(i), Counter(i as u64))).is_true();
+    }
+
+    assert_that(&rrd.archives.len()).is_equal_to(2);
+    assert_that(&rrd.finest().resolution()).is_equal_to(chrono::Duration::seconds(1));
+    assert_that(&rrd.finest().first_timestamp()).is_equal_to(start + chrono::Duration::seconds(20));
+
+    let coarse = &rrd.archives[1];
+    assert_that(
+        &coarse
+            .iter()
+            .map(|(timestamp, counter)| (timestamp, *counter))
+            .collect::<Vec<_>>(),
+    )
+    .is_equal_to(vec![
+        (start, Counter(9)),
+        (start + chrono::Duration::seconds(10), Counter(19)),
+        (start + chrono::Duration::seconds(20), Counter(29)),
+    ]);
+
+    assert_that(
+        &rrd.covering(start + chrono::Duration::seconds(25))
+            .resolution(),
+    )
+    .is_equal_to(chrono::Duration::seconds(1));
+    assert_that(
+        &rrd.covering(start + chrono::Duration::seconds(5))
+            .resolution(),
+    )
+    .is_equal_to(chrono::Duration::seconds(10));
+    assert_that(
+        &rrd.covering(start - chrono::Duration::seconds(100))
+            .resolution(),
+    )
+    .is_equal_to(chrono::Duration::seconds(10));
+}
+
+#[test]
+fn test_persist_round_trip() {
+    let start = NaiveDateTime::new(
+        NaiveDate::from_ymd(2000, 1, 1),
+        NaiveTime::from_hms(0, 0, 0),
+    );
+    let archives = [
+        Archive {
+            resolution: Duration::from_secs(1),
+            retain: Duration::from_secs(10),
+        },
+        Archive {
+            resolution: Duration::from_secs(10),
+            retain: Duration::from_secs(60),
+        },
+    ];
+    let mut rrd = MultiRRD::<Counter>::new(start, &archives);
+
+    // Wrap around the ring of the finest archive
+    for i in 0..25 {
+        rrd.put(start + chrono::Duration::seconds(i), Counter(i as u64 * 10));
+    }
+
+    let mut encoded = vec![];
+    rrd.write_to(&mut encoded).unwrap();
+    let mut restored = MultiRRD::<Counter>::read_from(&mut encoded.as_slice(), &archives).unwrap();
+
+    assert_that(&MultiRRD::<Counter>::read_from(&mut encoded.as_slice(), &archives[..1]).is_err())
+        .is_true();
+    for (archive, restored_archive) in rrd.archives.iter().zip(&restored.archives) {
+        assert_that(&restored_archive.iter().collect::<Vec<_>>())
+            .is_equal_to(archive.iter().collect::<Vec<_>>());
+    }
+
+    // The downtime is interpolated from the last restored value
+    assert_that(&restored.put(start + chrono::Duration::seconds(29), Counter(280))).is_true();
+    assert_that(&restored.finest().get(1))
+        .is_equal_to(Some((start + chrono::Duration::seconds(21), &Counter(210))));
+    assert_that(&restored.finest().get(6))
+        .is_equal_to(Some((start + chrono::Duration::seconds(26), &Counter(256))));
+    assert_that(&restored.finest().last())
+        .is_equal_to((start + chrono::Duration::seconds(29), &Counter(280)));
+
+    assert_that(&MultiRRD::<Counter>::read_from(&mut &encoded[..20], &archives).is_err()).is_true();
+
+    // A corrupt ring length is rejected before allocating the ring
+    let mut corrupt = encoded.clone();
+    corrupt[41..49].copy_from_slice(&u64::MAX.to_le_bytes());
+    let error = MultiRRD::<Counter>::read_from(&mut corrupt.as_slice(), &archives).unwrap_err();
+
+    assert_that(&error.kind()).is_equal_to(io::ErrorKind::InvalidData);
+
+    // Indices that do not match the timestamps would make `put` index out of the ring
+    let mut corrupt = encoded.clone();
+    corrupt.copy_within(25..33, 33);
+    let error = MultiRRD::<Counter>::read_from(&mut corrupt.as_slice(), &archives).unwrap_err();
+
+    assert_that(&error.kind()).is_equal_to(io::ErrorKind::InvalidData);
+
+    // Timestamps off the resolution
+    let mut corrupt = encoded.clone();
+    for range in &[9..17, 17..25] {
+        let timestamp = i64::from_le_bytes(encoded[range.clone()].try_into().unwrap());
+        corrupt[range.clone()].copy_from_slice(&(timestamp + 1).to_le_bytes());
+    }
+    let error = MultiRRD::<Counter>::read_from(&mut corrupt.as_slice(), &archives).unwrap_err();
+
+    assert_that(&error.kind()).is_equal_to(io::ErrorKind::InvalidData);
+
+    let mut corrupt = encoded;
+    corrupt[0] = u8::MAX;
+
+    assert_that(&MultiRRD::<Counter>::read_from(&mut corrupt.as_slice(), &archives).is_err())
+        .is_true();
+}
+
+#[test]
+fn test_range_and_resample() {
+    let start = NaiveDateTime::new(
+        NaiveDate::from_ymd(2000, 1, 1),
+        NaiveTime::from_hms(0, 0, 0),
+    );
+    let at = |secs: i64| start + chrono::Duration::seconds(secs);
+    let mut rrd = RRD::<Counter>::new(start, Duration::from_secs(1), Duration::from_secs(60));
+
+    for i in 0..30 {
+        rrd.put(at(i), Counter(i as u64 * 10));
+    }
+
+    assert_that(&rrd.range(at(5), at(8)).collect::<Vec<_>>()).is_equal_to(vec![
+        (at(5), &Counter(50)),
+        (at(6), &Counter(60)),
+        (at(7), &Counter(70)),
+        (at(8), &Counter(80)),
+    ]);
+    assert_that(&rrd.range(at(40), at(50)).count()).is_equal_to(0);
+
+    let step = chrono::Duration::seconds(5);
+    assert_that(&rrd.resample(at(10), at(19), step, Aggregator::Max))
+        .is_equal_to(vec![(at(10), Counter(140)), (at(15), Counter(190))]);
+    assert_that(&rrd.resample(at(10), at(19), step, Aggregator::Mean))
+        .is_equal_to(vec![(at(10), Counter(120)), (at(15), Counter(170))]);
+    assert_that(&rrd.resample(at(10), at(19), step, Aggregator::DeltaSum))
+        .is_equal_to(vec![(at(10), Counter(50)), (at(15), Counter(50))]);
+    // Nothing before the first entry to compare with
+    assert_that(&rrd.resample(at(0), at(4), step, Aggregator::DeltaSum))
+        .is_equal_to(vec![(at(0), Counter(40))]);
+    // Buckets past the last entry are empty
+    assert_that(&rrd.resample(at(25), at(34), step, Aggregator::Max))
+        .is_equal_to(vec![(at(25), Counter(290)), (at(30), Counter(0))]);
+
+    // Invalid steps and windows are empty instead of a panic or an enormous allocation
+    let resample = |from, to, step_micros| {
+        rrd.resample(
+            from,
+            to,
+            chrono::Duration::microseconds(step_micros),
+            Aggregator::DeltaSum,
+        )
+        .len()
+    };
+    assert_that(&resample(at(10), at(19), 0)).is_equal_to(0);
+    assert_that(&resample(at(10), at(19), 500)).is_equal_to(0);
+    assert_that(&resample(at(19), at(10), 5_000_000)).is_equal_to(0);
+    assert_that(&resample(at(0), at(30), 1_000)).is_equal_to(0);
+    // Steps are rounded to whole milliseconds
+    assert_that(&resample(at(10), at(19), 1_500)).is_equal_to(9001);
+}
@@ -0,0 +1,193 @@
+use super::{HostTraffic, RateSeries};
+use crate::common;
+use crate::leases::Lease;
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LeaseInfo {
+    pub name: Option<String>,
+    pub client_id: Option<String>,
+    pub mac: Option<String>,
+}
+
+impl LeaseInfo {
+    pub fn lookup(leases: &HashMap<IpAddr, Lease>, addr: &IpAddr) -> LeaseInfo {
+        leases
+            .get(addr)
+            .map(|lease| LeaseInfo {
+                name: lease.hostname().map(str::to_string),
+                client_id: Some(lease.client_id.clone()),
+                mac: lease.hardware_addr(),
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WithLease<T> {
+    #[serde(flatten)]
+    pub host: T,
+    #[serde(flatten)]
+    pub lease: LeaseInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Device {
+    pub name: Option<String>,
+    pub mac: Option<String>,
+    pub client_ids: Vec<String>,
+    pub addrs: Vec<IpAddr>,
+    #[serde(rename = "in")]
+    pub in_rates: RateSeries,
+    #[serde(rename = "out")]
+    pub out_rates: RateSeries,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum DeviceKey {
+    Lease(String),
+    Addr(IpAddr),
+}
+
+impl Device {
+    pub fn group(hosts: Vec<HostTraffic>, leases: &HashMap<IpAddr, Lease>) -> Vec<Device> {
+        let mut groups: BTreeMap<DeviceKey, Vec<HostTraffic>> = BTreeMap::new();
+
+        for host in hosts {
+            let key = match leases.get(&host.addr) {
+                Some(lease) => DeviceKey::Lease(lease.device_key()),
+                None => DeviceKey::Addr(host.addr),
+            };
+            groups.entry(key).or_default().push(host);
+        }
+
+        groups
+            .into_values()
+            .filter_map(|hosts| Device::from_hosts(hosts, leases))
+            .collect()
+    }
+
+    fn from_hosts(hosts: Vec<HostTraffic>, leases: &HashMap<IpAddr, Lease>) -> Option<Device> {
+        let resolution = chrono::Duration::from_std(common::RESOLUTION).unwrap();
+        let host_leases = hosts
+            .iter()
+            .filter_map(|host| leases.get(&host.addr))
+            .collect::<Vec<&Lease>>();
+        let mut addrs = hosts.iter().map(|host| host.addr).collect::<Vec<IpAddr>>();
+        let mut in_series = Vec::with_capacity(hosts.len());
+        let mut out_series = Vec::with_capacity(hosts.len());
+
+        addrs.sort();
+        for host in hosts {
+            in_series.push((host.in_rates.start, host.in_rates.rates));
+            out_series.push((host.out_rates.start, host.out_rates.rates));
+        }
+
+        Some(Device {
+            name: host_leases
+                .iter()
+                .find_map(|lease| lease.hostname())
+                .map(str::to_string),
+            mac: host_leases.iter().find_map(|lease| lease.hardware_addr()),
+            client_ids: host_leases
+                .iter()
+                .map(|lease| lease.client_id.clone())
+                .collect::<BTreeSet<String>>()
+                .into_iter()
+                .collect(),
+            addrs,
+            in_rates: common::sum_rates(resolution, in_series)?.into(),
+            out_rates: common::sum_rates(resolution, out_series)?.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+    use spectral::prelude::*;
+
+    fn host(addr: &str) -> HostTraffic {
+        let start = NaiveDateTime::new(
+            NaiveDate::from_ymd(2000, 1, 1),
+            NaiveTime::from_hms(0, 0, 0),
+        );
+
+        HostTraffic {
+            addr: addr.parse().unwrap(),
+            in_rates: (start, vec![Default::default(); 2]).into(),
+            out_rates: (start, vec![Default::default(); 2]).into(),
+            remotes: vec![],
+        }
+    }
+
+    fn lease(name: &str, addr: &str, mac: Option<&str>, client_id: &str) -> (IpAddr, Lease) {
+        let addr = addr.parse().unwrap();
+
+        (
+            addr,
+            Lease {
+                name: name.to_string(),
+                addr,
+                mac: mac.map(str::to_string),
+                client_id: client_id.to_string(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_group_by_hardware_addr() {
+        let leases = vec![
+            lease(
+                "brick",
+                "192.168.3.86",
+                Some("24:5e:be:12:34:56"),
+                "01:24:5e:be:12:34:56",
+            ),
+            lease("*", "1234::86", None, "00:03:00:01:24:5e:be:12:34:56"),
+            lease(
+                "thunder",
+                "1234::28a",
+                None,
+                "00:04:2e:3b:43:05:a5:df:ad:a0:32:bb:a8:a8:d3:12:34:56",
+            ),
+        ]
+        .into_iter()
+        .collect::<HashMap<IpAddr, Lease>>();
+        let hosts = vec![
+            host("192.168.3.86"),
+            host("1234::86"),
+            host("1234::28a"),
+            host("192.168.3.99"),
+        ];
+
+        let devices = Device::group(hosts, &leases);
+
+        assert_that(&devices.len()).is_equal_to(3);
+
+        let brick = devices
+            .iter()
+            .find(|device| device.name == Some("brick".to_string()))
+            .unwrap();
+        assert_that(&brick.mac).contains_value("24:5e:be:12:34:56".to_string());
+        assert_that(&brick.addrs).is_equal_to(vec![
+            "192.168.3.86".parse::<IpAddr>().unwrap(),
+            "1234::86".parse::<IpAddr>().unwrap(),
+        ]);
+        assert_that(&brick.client_ids.len()).is_equal_to(2);
+
+        let thunder = devices
+            .iter()
+            .find(|device| device.name == Some("thunder".to_string()))
+            .unwrap();
+        assert_that(&thunder.mac).is_none();
+        assert_that(&thunder.addrs.len()).is_equal_to(1);
+
+        let anonymous = devices.iter().find(|device| device.name.is_none()).unwrap();
+        assert_that(&anonymous.addrs).is_equal_to(vec!["192.168.3.99".parse::<IpAddr>().unwrap()]);
+        assert_that(&anonymous.client_ids).is_empty();
+    }
+}
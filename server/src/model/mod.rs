@@ -1 +1,272 @@
+mod device;
+mod update;
 
+pub use device::*;
+pub use update::*;
+
+use crate::common::{TrafficCounter, TrafficRate, TrafficSnapshot};
+use crate::minirrd::Aggregator;
+use chrono::NaiveDateTime;
+use serde_derive::Serialize;
+use std::net::IpAddr;
+
+#[derive(Debug, Serialize)]
+pub struct RateSeries {
+    pub start: NaiveDateTime,
+    pub rates: Vec<TrafficRate>,
+}
+
+impl From<(NaiveDateTime, Vec<TrafficRate>)> for RateSeries {
+    fn from((start, rates): (NaiveDateTime, Vec<TrafficRate>)) -> Self {
+        RateSeries { start, rates }
+    }
+}
+
+/// Resample `from..=to` to buckets of `step`
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub step: chrono::Duration,
+    pub aggregator: Aggregator,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WanTraffic {
+    pub interface: String,
+    #[serde(rename = "in")]
+    pub in_rates: RateSeries,
+    #[serde(rename = "out")]
+    pub out_rates: RateSeries,
+}
+
+impl WanTraffic {
+    pub fn new(interface: String, snapshot: TrafficSnapshot) -> WanTraffic {
+        WanTraffic {
+            interface,
+            in_rates: (snapshot.start, snapshot.in_rates).into(),
+            out_rates: (snapshot.start, snapshot.out_rates).into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectorHealth {
+    #[serde(skip)]
+    pub started: NaiveDateTime,
+    pub last_success: Option<NaiveDateTime>,
+    pub entries: usize,
+    pub errors: u64,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub warning: Option<String>,
+}
+
+impl CollectorHealth {
+    /// A collector that never succeeded is considered stale once it is running longer than `threshold`
+    pub fn is_stale(&self, now: NaiveDateTime, threshold: chrono::Duration) -> bool {
+        now - self.last_success.unwrap_or(self.started) > threshold
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub conntrack: CollectorHealth,
+    pub device: CollectorHealth,
+    pub leases: CollectorHealth,
+}
+
+impl Health {
+    pub fn collectors(&self) -> [&CollectorHealth; 3] {
+        [&self.conntrack, &self.device, &self.leases]
+    }
+
+    /// Healthy as long as no collector is stale
+    pub fn is_healthy(&self, now: NaiveDateTime, threshold: chrono::Duration) -> bool {
+        self.collectors()
+            .iter()
+            .all(|collector| !collector.is_stale(now, threshold))
+    }
+
+    /// Ready once every collector has succeeded at least once and none is stale
+    pub fn is_ready(&self, now: NaiveDateTime, threshold: chrono::Duration) -> bool {
+        self.collectors().iter().all(|collector| {
+            collector.last_success.is_some() && !collector.is_stale(now, threshold)
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthStatus {
+    pub healthy: bool,
+    #[serde(flatten)]
+    pub health: Health,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostSummary {
+    pub addr: IpAddr,
+    pub group: Option<String>,
+    pub remotes: usize,
+}
+
+/// Traffic of all local hosts of a named subnet
+#[derive(Debug, Serialize)]
+pub struct GroupTraffic {
+    pub name: String,
+    pub hosts: usize,
+    #[serde(rename = "in")]
+    pub in_rate: TrafficRate,
+    #[serde(rename = "out")]
+    pub out_rate: TrafficRate,
+    pub in_total: TrafficCounter,
+    pub out_total: TrafficCounter,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoteTraffic {
+    pub addr: IpAddr,
+    #[serde(rename = "in")]
+    pub in_rates: RateSeries,
+    #[serde(rename = "out")]
+    pub out_rates: RateSeries,
+}
+
+impl RemoteTraffic {
+    pub fn new(addr: IpAddr, snapshot: TrafficSnapshot) -> RemoteTraffic {
+        RemoteTraffic {
+            addr,
+            in_rates: (snapshot.start, snapshot.in_rates).into(),
+            out_rates: (snapshot.start, snapshot.out_rates).into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostVolume {
+    pub addr: IpAddr,
+    pub since: NaiveDateTime,
+    #[serde(rename = "in")]
+    pub in_volume: TrafficCounter,
+    #[serde(rename = "out")]
+    pub out_volume: TrafficCounter,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoteRate {
+    pub addr: IpAddr,
+    #[serde(rename = "in")]
+    pub in_rate: TrafficRate,
+    #[serde(rename = "out")]
+    pub out_rate: TrafficRate,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostTraffic {
+    pub addr: IpAddr,
+    #[serde(rename = "in")]
+    pub in_rates: RateSeries,
+    #[serde(rename = "out")]
+    pub out_rates: RateSeries,
+    pub remotes: Vec<RemoteRate>,
+}
+
+impl HostTraffic {
+    pub fn current_bytes_per_sec(&self) -> u64 {
+        self.in_rates
+            .rates
+            .last()
+            .map_or(0, TrafficRate::bytes_per_sec)
+            + self
+                .out_rates
+                .rates
+                .last()
+                .map_or(0, TrafficRate::bytes_per_sec)
+    }
+}
+
+#[derive(Debug)]
+pub struct WanCounters {
+    pub interface: String,
+    pub in_count: TrafficCounter,
+    pub out_count: TrafficCounter,
+}
+
+#[derive(Debug)]
+pub struct RemoteCounters {
+    pub addr: IpAddr,
+    pub in_count: TrafficCounter,
+    pub out_count: TrafficCounter,
+}
+
+#[derive(Debug)]
+pub struct HostCounters {
+    pub addr: IpAddr,
+    pub in_count: TrafficCounter,
+    pub out_count: TrafficCounter,
+    pub remotes: Vec<RemoteCounters>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use spectral::prelude::*;
+
+    fn collector(started: NaiveDateTime, last_success: Option<NaiveDateTime>) -> CollectorHealth {
+        CollectorHealth {
+            started,
+            last_success,
+            entries: 0,
+            errors: 0,
+            consecutive_failures: 0,
+            last_error: None,
+            warning: None,
+        }
+    }
+
+    fn health(device: CollectorHealth, started: NaiveDateTime) -> Health {
+        Health {
+            conntrack: collector(started, Some(started)),
+            device,
+            leases: collector(started, Some(started)),
+        }
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let started = NaiveDateTime::from_timestamp(1_560_000_000, 0);
+        let threshold = Duration::seconds(30);
+
+        let succeeded = collector(started, Some(started + Duration::seconds(10)));
+        assert_that(&succeeded.is_stale(started + Duration::seconds(40), threshold)).is_false();
+        assert_that(&succeeded.is_stale(started + Duration::seconds(41), threshold)).is_true();
+
+        let never_succeeded = collector(started, None);
+        assert_that(&never_succeeded.is_stale(started + Duration::seconds(30), threshold))
+            .is_false();
+        assert_that(&never_succeeded.is_stale(started + Duration::seconds(31), threshold))
+            .is_true();
+    }
+
+    #[test]
+    fn test_healthy_and_ready() {
+        let started = NaiveDateTime::from_timestamp(1_560_000_000, 0);
+        let now = started + Duration::seconds(20);
+        let threshold = Duration::seconds(30);
+
+        let healthy = health(collector(started, Some(now)), now);
+        assert_that(&healthy.is_healthy(now, threshold)).is_true();
+        assert_that(&healthy.is_ready(now, threshold)).is_true();
+
+        // Not ready, but still healthy within the threshold after startup
+        let never_succeeded = health(collector(started, None), now);
+        assert_that(&never_succeeded.is_healthy(now, threshold)).is_true();
+        assert_that(&never_succeeded.is_ready(now, threshold)).is_false();
+
+        let later = started + Duration::seconds(60);
+        let stale = health(collector(started, Some(started)), later);
+        assert_that(&stale.is_healthy(later, threshold)).is_false();
+        assert_that(&stale.is_ready(later, threshold)).is_false();
+    }
+}
@@ -0,0 +1,50 @@
+use crate::common::TrafficRate;
+use actix::{Message, Recipient};
+use chrono::NaiveDateTime;
+use serde_derive::Serialize;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostRate {
+    pub addr: IpAddr,
+    #[serde(rename = "in")]
+    pub in_rate: TrafficRate,
+    #[serde(rename = "out")]
+    pub out_rate: TrafficRate,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RatesUpdate {
+    Wan {
+        timestamp: NaiveDateTime,
+        interface: String,
+        #[serde(rename = "in")]
+        in_rate: TrafficRate,
+        #[serde(rename = "out")]
+        out_rate: TrafficRate,
+    },
+    Hosts {
+        timestamp: NaiveDateTime,
+        hosts: Vec<HostRate>,
+    },
+}
+
+impl Message for RatesUpdate {
+    type Result = ();
+}
+
+/// Updates are only built while `active` is set, i.e. while someone is actually listening
+#[derive(Message)]
+pub struct Subscribe {
+    pub recipient: Recipient<RatesUpdate>,
+    pub active: Arc<AtomicBool>,
+}
+
+impl Subscribe {
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+}
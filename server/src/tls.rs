@@ -0,0 +1,33 @@
+use crate::config::TlsConfig;
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{NoClientAuth, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind};
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+pub fn server_config(tls: &TlsConfig) -> io::Result<ServerConfig> {
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    let cert_chain = certs(&mut BufReader::new(File::open(&tls.certificate)?))
+        .map_err(|_| invalid_data(format!("Invalid certificate: {}", tls.certificate)))?;
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(File::open(&tls.private_key)?))
+        .map_err(|_| invalid_data(format!("Invalid private key: {}", tls.private_key)))?;
+
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(File::open(&tls.private_key)?))
+            .map_err(|_| invalid_data(format!("Invalid private key: {}", tls.private_key)))?;
+    }
+    if cert_chain.is_empty() || keys.is_empty() {
+        return Err(invalid_data(format!(
+            "No certificate or private key found in {} / {}",
+            tls.certificate, tls.private_key
+        )));
+    }
+    server_config
+        .set_single_cert(cert_chain, keys.remove(0))
+        .map_err(|error| invalid_data(format!("Invalid certificate: {}", error)))?;
+
+    Ok(server_config)
+}
@@ -0,0 +1,14 @@
+use actix_web::{web, HttpResponse};
+
+const INDEX_HTML: &str = include_str!("../static/index.html");
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/index.html", web::get().to(index));
+}
+
+fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(INDEX_HTML)
+}
@@ -9,7 +9,7 @@ impl Distribution<char> for LowerAlpha {
         const RANGE: u32 = 26;
         const GEN_ASCII_STR_CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
 
-        return GEN_ASCII_STR_CHARSET[(rng.next_u32() % RANGE) as usize] as char;
+        GEN_ASCII_STR_CHARSET[(rng.next_u32() % RANGE) as usize] as char
     }
 }
 
@@ -20,7 +20,7 @@ impl Distribution<char> for LowerAlphanumeric {
         const RANGE: u32 = 26 + 10;
         const GEN_ASCII_STR_CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
 
-        return GEN_ASCII_STR_CHARSET[(rng.next_u32() % RANGE) as usize] as char;
+        GEN_ASCII_STR_CHARSET[(rng.next_u32() % RANGE) as usize] as char
     }
 }
 